@@ -0,0 +1,102 @@
+use {
+    log::debug,
+    serde_derive::{Deserialize, Serialize},
+    std::{
+        collections::HashMap,
+        fs::File,
+        io::{self, Read as _, Write as _},
+        path::Path,
+        time::{Duration, SystemTime},
+    },
+};
+
+/// What we know about the last successful build of a page
+#[derive(Debug, Deserialize, Serialize)]
+struct PageState {
+    /// Hash of the template content, as a hex string
+    hash: String,
+    /// When the page was built, in nanoseconds since the Unix epoch
+    built: i64,
+}
+
+/// Persistent state used for deciding which pages need to be rebuilt
+#[derive(Default, Debug, Deserialize, Serialize)]
+pub struct BuildState {
+    pages: HashMap<String, PageState>,
+}
+
+/// Path of the build state file, relative to the project root
+pub const PATH: &str = ".noten/build-state.toml";
+
+fn hash_string(text: &str) -> String {
+    format!("{:016x}", crate::util::hash::fnv1a(text.as_bytes()))
+}
+
+fn timestamp(time: SystemTime) -> i64 {
+    let since_epoch = time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+    since_epoch.as_nanos().try_into().unwrap_or(i64::MAX)
+}
+
+impl BuildState {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut f = File::open(path)?;
+        let mut s = String::new();
+        f.read_to_string(&mut s)?;
+        toml::from_str(&s).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+    /// Whether the page generated from `template_path` needs to be rebuilt.
+    ///
+    /// A page is outdated if its output doesn't exist, its template content changed,
+    /// or any of `inputs` were modified after the page was last built.
+    pub fn is_outdated(
+        &self,
+        template_path: &Path,
+        template: &str,
+        out_path: &Path,
+        inputs: &[&Path],
+    ) -> bool {
+        let Some(state) = self.pages.get(&*template_path.to_string_lossy()) else {
+            debug!("{:?}: no previous build", template_path);
+            return true;
+        };
+        if !out_path.exists() {
+            debug!("{:?}: output {:?} is missing", template_path, out_path);
+            return true;
+        }
+        if state.hash != hash_string(template) {
+            debug!("{:?}: content hash changed", template_path);
+            return true;
+        }
+        let built = SystemTime::UNIX_EPOCH + Duration::from_nanos(state.built as u64);
+        for &input in std::iter::once(&template_path).chain(inputs) {
+            match crate::util::fs::modified(input) {
+                Some(modif) if modif <= built => {}
+                _ => {
+                    debug!("{:?}: {:?} is newer than the output", template_path, input);
+                    return true;
+                }
+            }
+        }
+        false
+    }
+    /// Records that the page generated from `template_path` was just built
+    pub fn record(&mut self, template_path: &Path, template: &str, built: SystemTime) {
+        self.pages.insert(
+            template_path.to_string_lossy().into_owned(),
+            PageState {
+                hash: hash_string(template),
+                built: timestamp(built),
+            },
+        );
+    }
+    /// Forgets about every page whose template is not in `template_paths`
+    pub fn retain(&mut self, template_paths: &[&Path]) {
+        self.pages
+            .retain(|k, _| template_paths.iter().any(|p| p.to_string_lossy() == *k));
+    }
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let string = toml::ser::to_string_pretty(self).unwrap();
+        let mut f = File::create(path)?;
+        f.write_all(string.as_bytes())
+    }
+}
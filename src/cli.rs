@@ -0,0 +1,66 @@
+use {
+    clap::{Parser, Subcommand},
+    noten::config::OnFailure,
+    std::{num::NonZeroUsize, path::PathBuf},
+};
+
+/// noten, the NOstressz Templating ENgine
+#[derive(Parser)]
+#[command(version, about)]
+pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// Path of the configuration file, relative to the project directory
+    #[arg(long, global = true, default_value = noten::config::FILENAME)]
+    pub config: PathBuf,
+    /// Run as if noten was started in this directory
+    #[arg(long, short = 'C', global = true)]
+    pub project_dir: Option<PathBuf>,
+    /// Write the output to this directory instead of `directories.output`
+    #[arg(long, short, global = true)]
+    pub output: Option<PathBuf>,
+    /// Stop at the first error, instead of continuing with the rest of the files
+    #[arg(long, global = true)]
+    pub fail_fast: bool,
+    /// How many pages to render in parallel [default: the number of CPUs]
+    #[arg(long, short, global = true)]
+    pub jobs: Option<NonZeroUsize>,
+    /// Always run the generators, instead of reusing their cached outputs
+    #[arg(long, global = true)]
+    pub no_gen_cache: bool,
+    /// Time limit for each run of a generator in seconds, 0 for none [default: `timeout` of the generator]
+    #[arg(long, global = true, value_name = "SECONDS", value_parser = noten::config::parse_timeout)]
+    pub gen_timeout: Option<f64>,
+    /// What to do when a generator fails: `fail`, `placeholder` or `cached` [default: `on-failure` of the generator]
+    #[arg(long, global = true, value_name = "POLICY")]
+    pub on_gen_failure: Option<OnFailure>,
+    /// Print debug information
+    #[arg(long, short, global = true, conflicts_with = "quiet")]
+    pub verbose: bool,
+    /// Only print errors
+    #[arg(long, short, global = true)]
+    pub quiet: bool,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Build the pages that are out of date (default)
+    Build,
+    /// Remove the output and the build state
+    Clean,
+    /// Process every template without writing any output, and report errors
+    Check,
+    /// Create a new project
+    New {
+        /// The directory to create the project in
+        dir: PathBuf,
+    },
+    /// Build, then rebuild whenever something changes
+    Watch,
+    /// Serve the output locally, rebuilding and reloading on changes
+    Serve {
+        /// The address to listen on
+        #[arg(long, default_value = noten::serve::DEFAULT_ADDR)]
+        addr: String,
+    },
+}
@@ -1,24 +1,222 @@
 use {
+    crate::{error::Error, util::glob::Glob},
     quick_error::quick_error,
     serde_derive::Deserialize,
-    std::{fs::File, io::Read as _},
+    std::{
+        collections::HashMap,
+        fs::File,
+        io::Read as _,
+        path::{Path, PathBuf},
+        time::Duration,
+    },
 };
 
 pub const FILENAME: &str = "noten.toml";
 
 #[derive(Debug, Deserialize)]
 pub struct Directories {
-    pub input: String,
-    pub output: String,
-    pub generators: Option<String>,
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub generators: Option<PathBuf>,
+    /// Where included files are looked up
+    #[serde(default = "default_partials")]
+    pub partials: PathBuf,
 }
 
-#[derive(Debug, Deserialize)]
+fn default_partials() -> PathBuf {
+    "partials".into()
+}
+
+impl Default for Directories {
+    fn default() -> Self {
+        Self {
+            input: PathBuf::new(),
+            output: PathBuf::new(),
+            generators: None,
+            partials: default_partials(),
+        }
+    }
+}
+
+/// Which non-template files get copied from the input to the output.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Assets {
+    /// If empty, every non-template file is included.
+    pub include: Vec<Glob>,
+    pub exclude: Vec<Glob>,
+}
+
+impl Assets {
+    /// Whether the file at `rel_path` (relative to the input directory) is an asset
+    pub fn is_asset(&self, rel_path: &Path) -> bool {
+        let included = self.include.is_empty() || self.include.iter().any(|g| g.matches(rel_path));
+        included && !self.exclude.iter().any(|g| g.matches(rel_path))
+    }
+}
+
+/// What to do when a generator fails
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnFailure {
+    /// Fail the page
+    #[default]
+    Fail,
+    /// Substitute the placeholder of the generator
+    Placeholder,
+    /// Substitute the last output of the generator with the same arguments, if there is one
+    Cached,
+}
+
+impl std::str::FromStr for OnFailure {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fail" => Ok(OnFailure::Fail),
+            "placeholder" => Ok(OnFailure::Placeholder),
+            "cached" => Ok(OnFailure::Cached),
+            _ => Err(format!(
+                "`{}` is not one of `fail`, `placeholder` and `cached`",
+                s
+            )),
+        }
+    }
+}
+
+/// Settings of a generator, in `[generators.<name>]`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Generator {
+    /// The program to run and its arguments. If not set, the generator is a Cargo project
+    /// in `directories.generators`.
+    pub command: Option<Vec<String>>,
+    /// A command to run once before the generator is first used during a build
+    pub build: Option<Vec<String>>,
+    /// The working directory of `build` and `command`. Defaults to the project directory.
+    pub dir: Option<PathBuf>,
+    /// Whether the output can be reused when the arguments and the binary didn't change
+    pub cacheable: bool,
+    /// Files the output depends on, besides the arguments
+    pub inputs: Vec<PathBuf>,
+    /// How many seconds a run of the generator may take. 0 means no limit.
+    #[serde(deserialize_with = "deserialize_timeout")]
+    pub timeout: f64,
+    pub on_failure: OnFailure,
+    /// Substituted when the generator fails, if `on_failure` is `placeholder`
+    pub placeholder: String,
+}
+
+impl Generator {
+    /// The time limit of a run, if any
+    pub fn timeout(&self) -> Option<Duration> {
+        Duration::try_from_secs_f64(self.timeout).ok().filter(|t| !t.is_zero())
+    }
+}
+
+/// Checks that `secs` is usable as a timeout
+fn check_timeout(secs: f64) -> Result<f64, String> {
+    if secs.is_finite() && secs >= 0.0 {
+        Ok(secs)
+    } else {
+        Err(format!(
+            "`{}` is not a timeout, which must be a number of seconds of at least 0",
+            secs
+        ))
+    }
+}
+
+fn deserialize_timeout<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    let secs = <f64 as serde::Deserialize>::deserialize(deserializer)?;
+    check_timeout(secs).map_err(serde::de::Error::custom)
+}
+
+/// Parses a timeout given on the command line, like `--gen-timeout`
+pub fn parse_timeout(s: &str) -> Result<f64, String> {
+    let secs = s.parse().map_err(|_| format!("`{}` is not a number", s))?;
+    check_timeout(secs)
+}
+
+impl Default for Generator {
+    fn default() -> Self {
+        Self {
+            command: None,
+            build: None,
+            dir: None,
+            cacheable: true,
+            inputs: Vec::new(),
+            timeout: 0.0,
+            on_failure: OnFailure::Fail,
+            placeholder: String::new(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
 pub struct Config {
-    pub skeleton: String,
+    /// The skeleton of pages that don't select one
+    pub skeleton: PathBuf,
+    /// Named skeletons, which pages can select with the `skeleton` attribute
+    #[serde(default)]
+    pub skeletons: HashMap<String, PathBuf>,
     pub index: String,
     pub directories: Directories,
     pub constants: toml::value::Table,
+    #[serde(default)]
+    pub assets: Assets,
+    #[serde(default)]
+    pub generators: HashMap<String, Generator>,
+    /// The path this configuration was read from
+    #[serde(skip)]
+    pub path: PathBuf,
+    /// The command line overrides that were applied to this configuration
+    #[serde(skip)]
+    pub overrides: Overrides,
+}
+
+/// Settings given on the command line, which take precedence over the configuration file
+#[derive(Debug, Default, Clone)]
+pub struct Overrides {
+    pub output: Option<PathBuf>,
+    /// Replaces the `timeout` of every generator
+    pub gen_timeout: Option<f64>,
+    /// Replaces the `on-failure` policy of every generator
+    pub on_gen_failure: Option<OnFailure>,
+}
+
+impl Config {
+    /// The settings of the generator `name`, with the command line overrides applied
+    pub fn generator(&self, name: &str) -> Generator {
+        let mut generator = self.generators.get(name).cloned().unwrap_or_default();
+        if let Some(timeout) = self.overrides.gen_timeout {
+            generator.timeout = timeout;
+        }
+        if let Some(on_failure) = self.overrides.on_gen_failure {
+            generator.on_failure = on_failure;
+        }
+        generator
+    }
+    /// Makes the paths in the configuration relative to `root`, rather than the current directory
+    pub fn resolve(&mut self, root: &Path) {
+        self.skeleton = root.join(&self.skeleton);
+        for skeleton in self.skeletons.values_mut() {
+            *skeleton = root.join(&*skeleton);
+        }
+        let dirs = &mut self.directories;
+        dirs.input = root.join(&dirs.input);
+        dirs.output = root.join(&dirs.output);
+        dirs.partials = root.join(&dirs.partials);
+        if let Some(generators) = &mut dirs.generators {
+            *generators = root.join(&*generators);
+        }
+        for generator in self.generators.values_mut() {
+            if let Some(dir) = &mut generator.dir {
+                *dir = root.join(&*dir);
+            }
+            for input in &mut generator.inputs {
+                *input = root.join(&*input);
+            }
+        }
+    }
 }
 
 quick_error! {
@@ -26,18 +224,57 @@ quick_error! {
     pub enum ReadError {
         Io(err: std::io::Error) {
             from()
+            display("{}", err)
         }
         De(err: toml::de::Error) {
             from()
+            display("{}", err)
         }
     }
 }
 
-/// Reads the configuration, returns (config, last-modified).
-pub fn read() -> Result<Config, ReadError> {
-    let mut file = File::open(FILENAME)?;
+/// Reads the configuration from `path`, and applies `overrides` to it.
+///
+/// The paths in the configuration are not resolved, see [`Config::resolve`].
+pub fn read(path: &Path, overrides: &Overrides) -> Result<Config, Error> {
+    let mut config = read_file(path).map_err(|e| Error::Config(path.into(), e))?;
+    if let Some(output) = &overrides.output {
+        config.directories.output = output.clone();
+    }
+    config.path = path.to_owned();
+    config.overrides = overrides.clone();
+    Ok(config)
+}
+
+fn read_file(path: &Path) -> Result<Config, ReadError> {
+    let mut file = File::open(path)?;
     let mut text = String::new();
     file.read_to_string(&mut text)?;
-    let config = toml::from_str(&text)?;
-    Ok(config)
+    Ok(toml::from_str(&text)?)
+}
+
+#[test]
+fn test_on_failure_from_str() {
+    assert_eq!("fail".parse(), Ok(OnFailure::Fail));
+    assert_eq!("placeholder".parse(), Ok(OnFailure::Placeholder));
+    assert_eq!("cached".parse(), Ok(OnFailure::Cached));
+    assert_eq!(
+        "Cached".parse::<OnFailure>(),
+        Err("`Cached` is not one of `fail`, `placeholder` and `cached`".into())
+    );
+}
+
+#[test]
+fn test_timeout() {
+    let timeout = |toml: &str| toml::from_str::<Generator>(toml).map(|g| g.timeout);
+    assert_eq!(timeout("timeout = 2").unwrap(), 2.0);
+    assert_eq!(timeout("timeout = 0.5").unwrap(), 0.5);
+    assert!(timeout("timeout = -1").is_err());
+    assert!(timeout("timeout = nan").is_err());
+    assert!(timeout("timeout = inf").is_err());
+    assert_eq!(parse_timeout("1.5"), Ok(1.5));
+    assert_eq!(parse_timeout("0"), Ok(0.0));
+    assert!(parse_timeout("-1").is_err());
+    assert!(parse_timeout("NaN").is_err());
+    assert_eq!(parse_timeout("soon"), Err("`soon` is not a number".into()));
 }
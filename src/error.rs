@@ -0,0 +1,293 @@
+use {
+    crate::config::ReadError,
+    quick_error::quick_error,
+    std::{
+        fmt, io,
+        path::{Path, PathBuf},
+    },
+};
+
+/// A span of text in a source file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    pub path: PathBuf,
+    /// Byte offset from the beginning of the file
+    pub offset: usize,
+    /// 1-based line number
+    pub line: usize,
+    /// 1-based column number, in characters
+    pub column: usize,
+    /// Length of the span in characters. Always at least 1, and never extends past the line.
+    pub len: usize,
+    /// The text of the line the span begins on
+    pub line_text: String,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.path.display(), self.line, self.column)
+    }
+}
+
+/// A source file that errors can point into
+pub struct Source<'a> {
+    pub path: &'a Path,
+    pub text: &'a str,
+}
+
+impl Source<'_> {
+    /// The location of the character at `offset`
+    pub fn location(&self, offset: usize) -> Location {
+        self.span(offset, 1)
+    }
+    /// The location of the `len` bytes starting at `offset`
+    pub fn span(&self, offset: usize, len: usize) -> Location {
+        let offset = offset.min(self.text.len());
+        let before = &self.text[..offset];
+        let line_start = before.rfind('\n').map_or(0, |pos| pos + 1);
+        let line_end = self.text[offset..].find('\n').map_or(self.text.len(), |pos| offset + pos);
+        let span_end = (offset + len).min(line_end);
+        let len = self.text.get(offset..span_end).map_or(0, |s| s.chars().count());
+        Location {
+            path: self.path.to_owned(),
+            offset,
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+            len: len.max(1),
+            line_text: self.text[line_start..line_end].trim_end_matches('\r').to_owned(),
+        }
+    }
+}
+
+/// An error message pointing at a location in a source file, with an optional note
+/// with more information, and an optional hint on how to fix it.
+#[derive(Debug)]
+pub struct Diagnostic {
+    // Boxed to keep `Error` small
+    pub loc: Box<Location>,
+    pub msg: String,
+    /// Can span multiple lines, e.g. the error output of a generator
+    pub note: Option<String>,
+    pub hint: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn new(loc: Location, msg: impl Into<String>) -> Self {
+        Self {
+            loc: Box::new(loc),
+            msg: msg.into(),
+            note: None,
+            hint: None,
+        }
+    }
+    pub fn note(mut self, note: impl Into<String>) -> Self {
+        self.note = Some(note.into());
+        self
+    }
+    pub fn hint(mut self, hint: impl Into<String>) -> Self {
+        self.hint = Some(hint.into());
+        self
+    }
+    /// An error of `kind`, e.g. `Error::Substitution`, at `loc`, with a hint on how to fix it
+    pub fn error(
+        kind: fn(Diagnostic) -> Error,
+        loc: &Location,
+        msg: impl Into<String>,
+        hint: impl Into<String>,
+    ) -> Error {
+        kind(Diagnostic::new(loc.clone(), msg).hint(hint))
+    }
+}
+
+/// Displays in the style of compiler error messages:
+///
+/// ```text
+/// Constant `phnoe` does not exist
+///  --> src/index.noten:3:9
+///   |
+/// 3 | Call {{const phnoe}}
+///   |              ^^^^^
+///   = hint: Did you mean `phone`?
+/// ```
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let loc = &self.loc;
+        let line_no = loc.line.to_string();
+        let gutter = " ".repeat(line_no.len());
+        // Keep tabs, so the caret lines up with the source line
+        let padding: String = loc
+            .line_text
+            .chars()
+            .take(loc.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        writeln!(f, "{}", self.msg)?;
+        writeln!(f, "{}--> {}", gutter, loc)?;
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", line_no, loc.line_text)?;
+        write!(f, "{} | {}{}", gutter, padding, "^".repeat(loc.len))?;
+        if let Some(note) = &self.note {
+            let prefix = format!("{} = note: ", gutter);
+            // Continuation lines are aligned with the first one
+            let indent = " ".repeat(prefix.len());
+            for (i, line) in note.lines().enumerate() {
+                let prefix = if i == 0 { &prefix } else { &indent };
+                write!(f, "\n{}{}", prefix, line)?;
+            }
+        }
+        if let Some(hint) = &self.hint {
+            write!(f, "\n{} = hint: {}", gutter, hint)?;
+        }
+        Ok(())
+    }
+}
+
+/// Finds the candidate that is most likely what the user meant when they wrote `name`.
+pub fn closest_match<'a>(
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Option<&'a str> {
+    // Allow roughly one typo per three characters
+    let max_distance = (name.chars().count() / 3).max(1);
+    candidates
+        .into_iter()
+        .map(|c| (edit_distance(name, c), c))
+        .filter(|&(dist, _)| dist <= max_distance)
+        .min_by_key(|&(dist, _)| dist)
+        .map(|(_, c)| c)
+}
+
+/// A hint suggesting the candidate that is most likely what the user meant by `name`, if any
+pub fn did_you_mean<'a>(
+    name: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Option<String> {
+    closest_match(name, candidates).map(|similar| format!("Did you mean `{}`?", similar))
+}
+
+/// Formats `words` like "`a`, `b` and `c`"
+pub fn list<'a>(words: impl IntoIterator<Item = &'a str>) -> String {
+    let quoted: Vec<String> = words.into_iter().map(|w| format!("`{}`", w)).collect();
+    match quoted.split_last() {
+        Some((last, init)) if !init.is_empty() => format!("{} and {}", init.join(", "), last),
+        _ => quoted.concat(),
+    }
+}
+
+/// Edit distance between `a` and `b`, where swapping two adjacent characters counts
+/// as a single edit (optimal string alignment distance)
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1).min(d[i][j - 1] + 1).min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+quick_error! {
+    #[derive(Debug)]
+    pub enum Error {
+        Config(path: PathBuf, err: ReadError) {
+            display("{}: {}", path.display(), err)
+            source(err)
+        }
+        Attribute(diag: Diagnostic) {
+            display("{}", diag)
+        }
+        Substitution(diag: Diagnostic) {
+            display("{}", diag)
+        }
+        Skeleton(diag: Diagnostic) {
+            display("{}", diag)
+        }
+        Generator(name: String, diag: Diagnostic) {
+            display("{}", diag)
+        }
+        GeneratorBuild(name: String, msg: String) {
+            display("Failed to build generator `{}`: {}", name, msg)
+        }
+        Io(path: PathBuf, err: io::Error) {
+            display("{}: {}", path.display(), err)
+            source(err)
+        }
+    }
+}
+
+impl Error {
+    /// A single line description of the error, including its location
+    pub fn summary(&self) -> String {
+        match self {
+            Error::Attribute(diag)
+            | Error::Substitution(diag)
+            | Error::Skeleton(diag)
+            | Error::Generator(_, diag) => format!("{}: {}", diag.loc, diag.msg),
+            _ => self.to_string().lines().next().unwrap_or_default().to_owned(),
+        }
+    }
+}
+
+/// The diagnostic that `result` failed with, for tests of error messages
+#[cfg(test)]
+pub(crate) fn expect_diagnostic<T>(result: Result<T, Error>) -> Diagnostic {
+    match result {
+        Ok(_) => panic!("Expected an error"),
+        Err(
+            Error::Attribute(diag)
+            | Error::Substitution(diag)
+            | Error::Skeleton(diag)
+            | Error::Generator(_, diag),
+        ) => diag,
+        Err(e) => panic!("Expected an error with a diagnostic: {}", e),
+    }
+}
+
+#[test]
+fn test_closest_match() {
+    let names = ["phone", "email", "address"];
+    assert_eq!(closest_match("phnoe", names), Some("phone"));
+    assert_eq!(closest_match("emial", names), Some("email"));
+    assert_eq!(closest_match("adress", names), Some("address"));
+    assert_eq!(closest_match("title", names), None);
+    assert_eq!(
+        did_you_mean("phnoe", names).as_deref(),
+        Some("Did you mean `phone`?")
+    );
+    assert_eq!(did_you_mean("title", names), None);
+}
+
+#[test]
+fn test_diagnostic() {
+    let src = Source {
+        path: "a.noten".as_ref(),
+        text: "first\n\tsécond {{x}}\r\nthird",
+    };
+    let loc = src.span(src.text.find("{{").unwrap(), 5);
+    assert_eq!((loc.line, loc.column, loc.len), (2, 9, 5));
+    assert_eq!(loc.to_string(), "a.noten:2:9");
+    let loc = src.location(0);
+    assert_eq!((loc.line, loc.column), (1, 1));
+    let diag = Diagnostic::new(src.span(src.text.find("x").unwrap(), 1), "Bad x").hint("Fix it");
+    assert_eq!(
+        diag.to_string(),
+        "Bad x\n --> a.noten:2:11\n  |\n2 | \tsécond {{x}}\n  | \t         ^\n  = hint: Fix it"
+    );
+    let diag = Diagnostic::new(src.location(0), "Bad").note("one\ntwo").hint("Fix it");
+    assert_eq!(
+        diag.to_string(),
+        "Bad\n --> a.noten:1:1\n  |\n1 | first\n  | ^\n  = note: one\n          two\n  = hint: Fix it"
+    );
+}
@@ -0,0 +1,666 @@
+use {
+    crate::{
+        config::{self, Config},
+        error::Error,
+        util::hash::fnv1a,
+    },
+    log::{debug, info, warn},
+    std::{
+        collections::{HashMap, HashSet},
+        fs,
+        path::{Path, PathBuf},
+        process::Command,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex, OnceLock,
+        },
+        time::{Duration, Instant},
+    },
+};
+
+/// Path of the generator output cache, relative to the project root
+pub const CACHE_PATH: &str = ".noten/gen-cache";
+
+/// Why a generator couldn't be built
+pub enum BuildFailure {
+    /// The configuration of the generator is invalid
+    Invalid(String),
+    /// The build failed just now. The error includes the output of the build.
+    New(Error),
+    /// The build already failed earlier during this run, and was reported then
+    Reported,
+}
+
+/// Why running a generator failed
+pub struct RunFailure {
+    pub msg: String,
+    /// What the generator printed on stderr
+    pub stderr: String,
+    /// The last successful output with the same arguments, if there is one
+    pub last_output: Option<String>,
+}
+
+impl RunFailure {
+    fn new(msg: impl Into<String>) -> Self {
+        Self {
+            msg: msg.into(),
+            stderr: String::new(),
+            last_output: None,
+        }
+    }
+}
+
+/// What a generator is told about the page it's run for
+pub struct PageContext<'a> {
+    pub template: &'a Path,
+    pub output: &'a Path,
+    pub project_dir: &'a Path,
+    pub title: &'a str,
+    /// The global constants, merged with the local constants of the page, as JSON
+    pub constants: String,
+}
+
+impl PageContext<'_> {
+    /// The environment variables passed to the generator
+    fn env(&self) -> [(&'static str, String); 4] {
+        // The paths are made absolute, so they are usable regardless of the working directory
+        let path = |path: &Path| {
+            // An empty path means the current directory, e.g. for the project directory
+            let path = if path.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                path
+            };
+            let path = std::path::absolute(path).unwrap_or_else(|_| path.to_owned());
+            path.to_string_lossy().into_owned()
+        };
+        [
+            ("NOTEN_TEMPLATE", path(self.template)),
+            ("NOTEN_OUTPUT", path(self.output)),
+            ("NOTEN_PROJECT_DIR", path(self.project_dir)),
+            ("NOTEN_TITLE", self.title.to_owned()),
+        ]
+    }
+}
+
+/// How a generator is built and run
+enum Kind<'a> {
+    /// A Cargo project in `directories.generators`, which is the default
+    Cargo { project: PathBuf },
+    /// A command given in `[generators.<name>]`, with an optional build command
+    Command {
+        command: &'a [String],
+        build: Option<&'a [String]>,
+    },
+}
+
+/// A generator that was built successfully
+#[derive(Clone)]
+pub struct Built {
+    program: PathBuf,
+    /// Arguments passed before the ones given in the substitution
+    args: Vec<String>,
+    /// The working directory
+    dir: PathBuf,
+    /// The files the generator consists of. Pages that use it are rebuilt when they change.
+    pub files: Vec<PathBuf>,
+    /// Hash of `files`, if outputs are cached
+    hash: Option<u64>,
+}
+
+/// The generators used during a run.
+///
+/// Each generator is built at most once, the first time it's needed, even if
+/// pages are rendered in parallel.
+#[derive(Default)]
+pub struct Generators {
+    /// The build of each generator, or `None` if the build failed
+    builds: Mutex<HashMap<String, Arc<OnceLock<Option<Built>>>>>,
+    /// The working directory of generators that don't set `dir`
+    project_dir: PathBuf,
+    /// Where outputs are stored, or `None` if they aren't
+    cache_dir: Option<PathBuf>,
+    /// Whether cached outputs are reused when nothing changed.
+    ///
+    /// If not, outputs are still stored for the `cached` failure policy.
+    reuse_outputs: bool,
+    /// For naming the temporary files of cache entries uniquely
+    tmp_counter: AtomicUsize,
+    /// The cache entries looked up during the run
+    used_entries: Mutex<HashSet<PathBuf>>,
+}
+
+impl Generators {
+    /// Creates a registry for the project in `project_dir`, that stores generator
+    /// outputs in `cache_dir`, and reuses them if `reuse_outputs` is set
+    pub fn new(project_dir: &Path, cache_dir: PathBuf, reuse_outputs: bool) -> Self {
+        Self {
+            project_dir: project_dir.to_owned(),
+            cache_dir: Some(cache_dir),
+            reuse_outputs,
+            ..Default::default()
+        }
+    }
+    /// The working directory of a generator
+    pub(crate) fn dir(&self, settings: &config::Generator) -> PathBuf {
+        match &settings.dir {
+            Some(dir) => dir.clone(),
+            // An empty path means the current directory
+            None if self.project_dir.as_os_str().is_empty() => PathBuf::from("."),
+            None => self.project_dir.clone(),
+        }
+    }
+    /// Builds the generator `name`, unless it was already built during this run.
+    pub fn build(&self, name: &str, config: &Config) -> Result<Built, BuildFailure> {
+        let settings = config.generator(name);
+        let kind = kind(name, &settings, config).map_err(BuildFailure::Invalid)?;
+        // Only hold the lock of the map while looking up the slot, so that
+        // different generators can be built at the same time
+        let slot = self.builds.lock().unwrap().entry(name.to_owned()).or_default().clone();
+        let mut failure = None;
+        let built = slot.get_or_init(|| {
+            let dir = self.dir(&settings);
+            let built = match kind {
+                Kind::Cargo { project } => cargo_build(name, &project, dir),
+                Kind::Command { command, build } => command_build(name, command, build, dir),
+            };
+            match built.and_then(|built| self.hash(name, built)) {
+                Ok(built) => Some(built),
+                Err(e) => {
+                    failure = Some(e);
+                    None
+                }
+            }
+        });
+        match (built, failure) {
+            (Some(built), _) => Ok(built.clone()),
+            (None, Some(e)) => Err(BuildFailure::New(e)),
+            (None, None) => Err(BuildFailure::Reported),
+        }
+    }
+    /// Computes the hash of the files of `built`, if cached outputs are reused
+    fn hash(&self, name: &str, mut built: Built) -> Result<Built, Error> {
+        if self.cache_dir.is_none() || !self.reuse_outputs {
+            return Ok(built);
+        }
+        let mut hashes = Vec::new();
+        for file in &built.files {
+            let bytes = fs::read(file).map_err(|e| {
+                Error::GeneratorBuild(name.to_owned(), format!("Failed to read {:?}: {}", file, e))
+            })?;
+            hashes.extend_from_slice(&fnv1a(&bytes).to_le_bytes());
+        }
+        built.hash = Some(fnv1a(&hashes));
+        Ok(built)
+    }
+    /// The names of the generators that `deps`, the dependencies of a page, belong to
+    pub fn used_by(&self, deps: &[PathBuf], config: &Config) -> Vec<String> {
+        let mut names = Vec::new();
+        if let Some(generators_dir) = &config.directories.generators {
+            let projects = deps.iter().filter_map(|dep| generator_dir(dep, generators_dir));
+            names.extend(projects.filter_map(|dir| Some(dir.file_name()?.to_str()?.to_owned())));
+        }
+        for (name, settings) in &config.generators {
+            let Some(command) = &settings.command else {
+                continue;
+            };
+            let dir = self.dir(settings);
+            let uses_file = command.iter().any(|arg| deps.contains(&dir.join(arg)));
+            if uses_file || settings.inputs.iter().any(|input| deps.contains(input)) {
+                names.push(name.clone());
+            }
+        }
+        names.sort();
+        names.dedup();
+        names
+    }
+    /// Runs the generator `name` with `args` for `page`, or returns its cached output.
+    ///
+    /// The generator gets the page context and the `named` arguments in environment variables,
+    /// and the constants on stdin.
+    pub fn run(
+        &self,
+        name: &str,
+        built: &Built,
+        args: &[&str],
+        named: &[(String, String)],
+        page: &PageContext,
+        settings: &config::Generator,
+    ) -> Result<String, RunFailure> {
+        let mut env = page.env().to_vec();
+        env.extend(named.iter().map(|(key, value)| (&key[..], value.clone())));
+        let program = built.program.to_string_lossy();
+        let leading_args = built.args.iter().map(|arg| &arg[..]);
+        let command: Vec<&str> = [&program[..]].into_iter().chain(leading_args).collect();
+        let all_args = [&command[..], args].concat();
+        // The last output with the same arguments, regardless of the generator and its inputs.
+        // It's kept whatever the policy, so that a later run with `--on-gen-failure cached`
+        // can fall back on it.
+        let last_path = match &self.cache_dir {
+            Some(dir) => {
+                let key = cache_key(name, &all_args, &env, &page.constants, 0, &[])
+                    .map_err(RunFailure::new)?;
+                Some(self.use_entry(dir.join(format!("last-{:016x}", key))))
+            }
+            _ => None,
+        };
+        let cache_path = match (&self.cache_dir, built.hash) {
+            (Some(dir), Some(hash)) if settings.cacheable => {
+                let key = cache_key(
+                    name,
+                    &all_args,
+                    &env,
+                    &page.constants,
+                    hash,
+                    &settings.inputs,
+                )
+                .map_err(RunFailure::new)?;
+                Some(self.use_entry(dir.join(format!("{:016x}", key))))
+            }
+            _ => None,
+        };
+        if let Some(path) = &cache_path {
+            if let Ok(output) = fs::read_to_string(path) {
+                debug!("Using cached output of {} {:?}", name, args);
+                return Ok(output);
+            }
+        }
+        let output =
+            execute(built, args, &env, &page.constants, settings.timeout()).map_err(|e| {
+                let mut failure = RunFailure::new(e.msg);
+                failure.stderr = e.stderr;
+                failure.last_output =
+                    last_path.as_ref().and_then(|path| fs::read_to_string(path).ok());
+                failure
+            })?;
+        for path in cache_path.iter().chain(&last_path) {
+            if let Err(e) = self.store(path, &output) {
+                warn!("Failed to cache the output of {}: {}", name, e);
+            }
+        }
+        Ok(output)
+    }
+    /// Records that the cache entry at `path` is still in use, so it isn't pruned
+    fn use_entry(&self, path: PathBuf) -> PathBuf {
+        self.used_entries.lock().unwrap().insert(path.clone());
+        path
+    }
+    /// Deletes the cache entries that weren't used during the run.
+    ///
+    /// Only call this after a run that rendered every page, otherwise entries that are
+    /// still needed are lost.
+    pub fn prune(&self) -> std::io::Result<()> {
+        let Some(dir) = &self.cache_dir else {
+            return Ok(());
+        };
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let used = self.used_entries.lock().unwrap();
+        for entry in entries {
+            let path = entry?.path();
+            if !used.contains(&path) {
+                debug!("Removing unused cache entry {:?}", path);
+                fs::remove_file(&path)?;
+            }
+        }
+        Ok(())
+    }
+    /// Writes a cache entry.
+    ///
+    /// The entry is written to a temporary file first, so that a page rendered at the same
+    /// time never reads it half written.
+    fn store(&self, path: &Path, output: &str) -> std::io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let n = self.tmp_counter.fetch_add(1, Ordering::Relaxed);
+        let tmp = path.with_extension(format!("{}-{}.tmp", std::process::id(), n));
+        fs::write(&tmp, output)?;
+        fs::rename(&tmp, path)
+    }
+}
+
+/// How the generator `name` is built and run, according to its `settings`
+fn kind<'a>(
+    name: &str,
+    settings: &'a config::Generator,
+    config: &Config,
+) -> Result<Kind<'a>, String> {
+    if let Some(command) = &settings.command {
+        if command.is_empty() {
+            return Err(format!("`generators.{}.command` is empty", name));
+        }
+        if settings.build.as_ref().is_some_and(|build| build.is_empty()) {
+            return Err(format!("`generators.{}.build` is empty", name));
+        }
+        return Ok(Kind::Command {
+            command,
+            build: settings.build.as_deref(),
+        });
+    }
+    let Some(generators_dir) = &config.directories.generators else {
+        return Err(format!(
+            "`directories.generators` is not set, and `generators.{}.command` isn't either",
+            name
+        ));
+    };
+    let project = generators_dir.join(name);
+    if !project.exists() {
+        return Err(format!("{:?} does not exist", project));
+    }
+    Ok(Kind::Cargo { project })
+}
+
+/// Builds the Cargo generator `name` in `project` with `cargo build --release`
+fn cargo_build(name: &str, project: &Path, dir: PathBuf) -> Result<Built, Error> {
+    info!("Building generator {}", name);
+    let mut cmd = Command::new("cargo");
+    cmd.current_dir(project).arg("build").arg("--release");
+    run_build(name, "cargo build", &mut cmd)?;
+    let binary = project.join("target/release").join(name);
+    let program = std::path::absolute(&binary).unwrap_or_else(|_| binary.clone());
+    debug!("Gen command path is {:?}", binary);
+    Ok(Built {
+        program,
+        args: Vec::new(),
+        dir,
+        files: vec![binary],
+        hash: None,
+    })
+}
+
+/// Runs the `build` command of the generator `name` in `dir`, if it has one
+fn command_build(
+    name: &str,
+    command: &[String],
+    build: Option<&[String]>,
+    dir: PathBuf,
+) -> Result<Built, Error> {
+    if let Some([program, args @ ..]) = build {
+        info!("Building generator {}", name);
+        let mut cmd = Command::new(resolve_program(program, &dir));
+        cmd.current_dir(&dir).args(args);
+        run_build(name, "`build`", &mut cmd)?;
+    }
+    let [program, args @ ..] = command else {
+        unreachable!("The command was checked to be non-empty");
+    };
+    // Arguments like a script path are part of the generator too
+    let files = command.iter().map(|arg| dir.join(arg)).filter(|path| path.is_file()).collect();
+    Ok(Built {
+        program: resolve_program(program, &dir),
+        args: args.to_vec(),
+        dir,
+        files,
+        hash: None,
+    })
+}
+
+/// Runs `cmd`, the build command of generator `name`.
+///
+/// On failure, the error includes the output of the command.
+fn run_build(name: &str, what: &str, cmd: &mut Command) -> Result<(), Error> {
+    let fail = |msg: String| Error::GeneratorBuild(name.to_owned(), msg);
+    let output = cmd.output().map_err(|e| fail(format!("Failed to spawn {}: {}", what, e)))?;
+    // Cargo reports its progress and the errors on stderr, but other tools may use stdout
+    let mut log = String::from_utf8_lossy(&output.stdout).into_owned();
+    log.push_str(&String::from_utf8_lossy(&output.stderr));
+    if !output.status.success() {
+        return Err(fail(format!(
+            "{} failed ({})\n{}",
+            what,
+            output.status,
+            log.trim_end()
+        )));
+    }
+    debug!("{}", log.trim_end());
+    Ok(())
+}
+
+/// Resolves `program` against `dir` if it's a path like `./gen.sh`.
+///
+/// Paths are made absolute, because it's platform dependent whether relative ones are
+/// resolved against the working directory of the child. Bare names like `python3` are
+/// looked up in `PATH`.
+fn resolve_program(program: &str, dir: &Path) -> PathBuf {
+    let path = Path::new(program);
+    if path.components().count() > 1 {
+        let path = dir.join(path);
+        std::path::absolute(&path).unwrap_or(path)
+    } else {
+        path.to_owned()
+    }
+}
+
+/// If `dep` is the binary of a Cargo generator in `generators_dir`, returns the directory of the
+/// generator
+fn generator_dir<'a>(dep: &'a Path, generators_dir: &Path) -> Option<&'a Path> {
+    let dir = dep.ancestors().nth(3)?;
+    let binary = Path::new("target/release").join(dir.file_name()?);
+    (dir.parent() == Some(generators_dir) && dep.strip_prefix(dir) == Ok(&binary)).then_some(dir)
+}
+
+/// Runs the generator `built` with `args` and `env`, writing `stdin` to its standard input.
+///
+/// The generator is killed if it runs longer than `timeout`.
+/// Returns its output, which must be valid UTF-8.
+fn execute(
+    built: &Built,
+    args: &[&str],
+    env: &[(&str, String)],
+    stdin: &str,
+    timeout: Option<Duration>,
+) -> Result<String, RunFailure> {
+    use std::{
+        io::{Read, Write as _},
+        process::Stdio,
+        thread,
+    };
+
+    let spawn_failed = |e| RunFailure::new(format!("Failed to run {:?}: {}", built.program, e));
+    let mut child = Command::new(&built.program)
+        .args(&built.args)
+        .args(args)
+        .current_dir(&built.dir)
+        .envs(env.iter().map(|(k, v)| (k, v)))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(spawn_failed)?;
+    // Each pipe is handled on its own thread, so a generator that writes a lot before
+    // reading its input can't deadlock with us. The threads aren't scoped, because the pipes
+    // can outlive a generator that is killed, if it started other processes.
+    let mut child_stdin = child.stdin.take().expect("stdin is piped");
+    let stdin = stdin.to_owned();
+    let program = built.program.clone();
+    thread::spawn(move || match child_stdin.write_all(stdin.as_bytes()) {
+        // The generator doesn't have to read its input
+        Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => {
+            warn!("Failed to write the input of {:?}: {}", program, e)
+        }
+        _ => {}
+    });
+    fn read_all(mut pipe: impl Read + Send + 'static) -> thread::JoinHandle<Vec<u8>> {
+        thread::spawn(move || {
+            let mut buf = Vec::new();
+            // A read error just means that the output is cut short
+            let _ = pipe.read_to_end(&mut buf);
+            buf
+        })
+    }
+    let stdout = read_all(child.stdout.take().expect("stdout is piped"));
+    let stderr = read_all(child.stderr.take().expect("stderr is piped"));
+    let status = match timeout {
+        None => child.wait().map_err(spawn_failed)?,
+        Some(timeout) => {
+            let start = Instant::now();
+            loop {
+                if let Some(status) = child.try_wait().map_err(spawn_failed)? {
+                    break status;
+                }
+                if start.elapsed() >= timeout {
+                    // It may have exited in the meantime, so errors are ignored
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return Err(RunFailure::new(format!(
+                        "{:?} timed out after {:?}",
+                        built.program, timeout
+                    )));
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+    };
+    let stdout = stdout.join().unwrap();
+    let stderr = String::from_utf8_lossy(&stderr.join().unwrap()).trim_end().to_owned();
+    if !status.success() {
+        let mut failure = RunFailure::new(format!("{:?} failed ({})", built.program, status));
+        failure.stderr = stderr;
+        return Err(failure);
+    }
+    if !stderr.is_empty() {
+        debug!("{:?} printed on stderr:\n{}", built.program, stderr);
+    }
+    String::from_utf8(stdout)
+        .map_err(|e| RunFailure::new(format!("Output is not valid UTF-8: {}", e)))
+}
+
+/// The cache key of running generator `name` with `args`, `env` and `stdin`.
+///
+/// `binary_hash` is the hash of the generator binary, and `inputs` are the files
+/// the output depends on.
+fn cache_key(
+    name: &str,
+    args: &[&str],
+    env: &[(&str, String)],
+    stdin: &str,
+    binary_hash: u64,
+    inputs: &[PathBuf],
+) -> Result<u64, String> {
+    let mut key = Vec::new();
+    // Separate the parts with a NUL, so that e.g. the arguments `a b` and `ab` differ
+    let env = env.iter().map(|(_, v)| &v[..]);
+    for part in [name].into_iter().chain(args.iter().copied()).chain(env).chain([stdin]) {
+        key.extend_from_slice(part.as_bytes());
+        key.push(0);
+    }
+    key.extend_from_slice(&binary_hash.to_le_bytes());
+    for input in inputs {
+        let content =
+            fs::read(input).map_err(|e| format!("Failed to read input {:?}: {}", input, e))?;
+        key.extend_from_slice(&fnv1a(&content).to_le_bytes());
+    }
+    Ok(fnv1a(&key))
+}
+
+#[test]
+fn test_generator_dir() {
+    let gens = Path::new("site/gens");
+    let dir = |dep: &'static str| generator_dir(Path::new(dep), gens);
+    assert_eq!(
+        dir("site/gens/a/target/release/a"),
+        Some(Path::new("site/gens/a"))
+    );
+    assert_eq!(dir("site/gens/a/target/release/b"), None);
+    assert_eq!(dir("site/data/a/target/release/a"), None);
+    assert_eq!(dir("site/gens/a/data.csv"), None);
+}
+
+#[test]
+fn test_resolve_program() {
+    let dir = Path::new("/site");
+    assert_eq!(resolve_program("python3", dir), Path::new("python3"));
+    assert_eq!(resolve_program("./gen.sh", dir), Path::new("/site/gen.sh"));
+    assert_eq!(resolve_program("bin/gen", dir), Path::new("/site/bin/gen"));
+    assert_eq!(
+        resolve_program("/usr/bin/gen", dir),
+        Path::new("/usr/bin/gen")
+    );
+}
+
+#[test]
+fn test_cache_key() {
+    let env = [("NOTEN_TITLE", "Home".to_owned())];
+    let key = |args: &[&str], hash| cache_key("gen", args, &env, "{}", hash, &[]).unwrap();
+    assert_eq!(key(&["a", "b"], 1), key(&["a", "b"], 1));
+    assert_ne!(key(&["a", "b"], 1), key(&["ab"], 1));
+    assert_ne!(key(&["a", "b"], 1), key(&["a", "b"], 2));
+    assert_ne!(
+        key(&[], 1),
+        cache_key("other", &[], &env, "{}", 1, &[]).unwrap()
+    );
+    assert_ne!(
+        key(&[], 1),
+        cache_key("gen", &[], &env, "{\"a\":1}", 1, &[]).unwrap()
+    );
+    let other_env = [("NOTEN_TITLE", "About".to_owned())];
+    assert_ne!(
+        key(&[], 1),
+        cache_key("gen", &[], &other_env, "{}", 1, &[]).unwrap()
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_timeout() {
+    let built = Built {
+        program: "sleep".into(),
+        args: vec!["5".into()],
+        dir: ".".into(),
+        files: Vec::new(),
+        hash: None,
+    };
+    let start = Instant::now();
+    let failure = execute(&built, &[], &[], "", Some(Duration::from_millis(500))).unwrap_err();
+    assert!(start.elapsed() < Duration::from_secs(4));
+    assert_eq!(failure.msg, "\"sleep\" timed out after 500ms");
+    let built = Built {
+        args: vec!["0".into()],
+        ..built
+    };
+    assert!(execute(&built, &[], &[], "", Some(Duration::from_secs(5))).is_ok());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_last_output() {
+    let dir = std::env::temp_dir().join(format!("noten-last-output-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("data.txt"), "good").unwrap();
+    let generators = Generators::new(&dir, dir.join("cache"), true);
+    let settings = config::Generator {
+        command: Some(vec!["cat".into(), "data.txt".into()]),
+        dir: Some(dir.clone()),
+        cacheable: false,
+        ..Default::default()
+    };
+    let config = Config {
+        generators: [("data".to_owned(), settings.clone())].into(),
+        ..Default::default()
+    };
+    let built = generators.build("data", &config).ok().unwrap();
+    let page = PageContext {
+        template: "a.noten".as_ref(),
+        output: "a.html".as_ref(),
+        project_dir: &dir,
+        title: "A",
+        constants: "{}".into(),
+    };
+    assert_eq!(
+        generators.run("data", &built, &[], &[], &page, &settings).ok(),
+        Some("good".into())
+    );
+    fs::remove_file(dir.join("data.txt")).unwrap();
+    // The last output is kept even though the policy is `fail`, for `--on-gen-failure cached`
+    let failure = generators.run("data", &built, &[], &[], &page, &settings).err().unwrap();
+    assert_eq!(settings.on_failure, config::OnFailure::Fail);
+    assert_eq!(failure.last_output.as_deref(), Some("good"));
+    assert!(failure.stderr.contains("data.txt"));
+    let failure = generators.run("data", &built, &["other"], &[], &page, &settings).err().unwrap();
+    assert_eq!(failure.last_output, None);
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,52 @@
+//! noten, the NOstressz Templating ENgine.
+//!
+//! The main entry point is [`Project`]. To render a single template without a project,
+//! use [`render`].
+
+mod build_state;
+pub mod config;
+pub mod error;
+mod generators;
+pub mod new_project;
+mod process;
+mod project;
+pub mod serve;
+pub mod skeleton;
+mod substitution;
+mod template_deps;
+mod util;
+mod watch;
+
+pub use {
+    error::Error,
+    project::{report_failures, BuildOptions, Project},
+};
+
+use {config::Config, skeleton::Skeleton, std::path::Path};
+
+/// Renders the template `template` into `skeleton`, with the global constants `constants`.
+///
+/// Errors refer to the template as `<template>`, and its output as `<output>`.
+/// Generators are not available, since there is no project to look them up in.
+/// Partials are looked up relative to the current directory.
+pub fn render(
+    template: &str,
+    skeleton: &Skeleton,
+    constants: &toml::value::Table,
+) -> Result<String, Error> {
+    let config = Config {
+        constants: constants.clone(),
+        ..Default::default()
+    };
+    let mut template_deps = template_deps::TemplateDeps::default();
+    let mut context = process::ProcessingContext {
+        template_path: Path::new("<template>"),
+        output_path: Path::new("<output>"),
+        project_dir: Path::new(""),
+        template_deps: &mut template_deps,
+        config: &config,
+        generators: &Default::default(),
+        skeletons: &Default::default(),
+    };
+    process::process(template, &mut context, skeleton)
+}
@@ -1,154 +1,98 @@
-mod config;
-mod process;
-mod skeleton;
-mod substitution;
-mod template_deps;
-mod util;
+mod cli;
 
 use {
-    config::{Config, ReadError},
-    log::{error, warn},
-    std::{
-        fs::{self, File},
-        io::{Read as _, Write as _},
+    log::error,
+    noten::{
+        config::{self, ReadError},
+        report_failures, BuildOptions, Error, Project,
     },
 };
 
-fn run(config: &Config) {
-    use {process::ProcessingContext, std::path::Path, template_deps::TemplateDeps};
-
-    let skeleton = skeleton::Skeleton::parse_file(&config.skeleton).unwrap();
+fn main() {
+    use {
+        clap::Parser as _,
+        cli::{Args, Command},
+        log::LevelFilter,
+    };
 
-    let mut template_deps = if Path::new(template_deps::PATH).exists() {
-        TemplateDeps::open().unwrap()
+    let args = Args::parse();
+    let level = if args.verbose {
+        LevelFilter::Debug
+    } else if args.quiet {
+        LevelFilter::Error
     } else {
-        TemplateDeps::default()
+        LevelFilter::Info
     };
+    env_logger::Builder::new()
+        .filter_level(level)
+        .format_timestamp(None)
+        .format_target(false)
+        .parse_default_env()
+        .init();
 
-    let entries = match fs::read_dir(&config.directories.input) {
-        Ok(entries) => entries,
-        Err(e) => {
-            error!(
-                "Failed to read input directory {:?}: {}",
-                config.directories.input, e
-            );
-            return;
+    let root = args.project_dir.unwrap_or_default();
+    let command = args.command.unwrap_or(Command::Build);
+    if let Command::New { dir } = &command {
+        let dir = root.join(dir);
+        if let Err(e) = noten::new_project::create(&dir) {
+            error!("Failed to create project in {:?}: {}", dir, e);
+            std::process::exit(1);
         }
+        return;
+    }
+    let overrides = config::Overrides {
+        output: args.output,
+        gen_timeout: args.gen_timeout,
+        on_gen_failure: args.on_gen_failure,
     };
-    let mut out_files = Vec::new();
-    for en in entries {
-        let en = match en {
-            Ok(en) => en,
-            Err(e) => {
-                error!("Failed to read directory entry: {}", e);
-                return;
-            }
-        };
-
-        let path = en.path();
-        if path.extension() != Some("noten".as_ref()) {
-            warn!(
-                "Skipping {:?}, because it doesn't have .noten extension",
-                path
+    let project = match Project::open(root, &args.config, &overrides) {
+        Ok(project) => project,
+        Err(Error::Config(path, ReadError::Io(err))) => {
+            error!(
+                "Failed opening {} ({}). Not a valid noten project.",
+                path.display(),
+                err
             );
-            continue;
+            std::process::exit(1);
         }
-        let stem = path.file_stem().expect("File doesnt' have a stem. The fuck?").to_owned();
-        let mut out_filename = stem.clone();
-        out_filename.push(".html");
-        let out_path = AsRef::<Path>::as_ref(&config.directories.output).join(out_filename);
-        out_files.push(out_path.clone());
-        let mut dep_modifs = Vec::new();
-        if let Some(deps) = template_deps.hash_map.get(&path) {
-            for path in deps {
-                use std::process::Command;
-                match Command::new("cargo")
-                    .current_dir(path.parent().unwrap())
-                    .arg("build")
-                    .arg("--release")
-                    .status()
-                {
-                    Ok(status) if status.success() => {
-                        let meta = fs::metadata(path).unwrap();
-                        let modif = meta.modified().unwrap();
-                        dep_modifs.push(modif);
-                    }
-                    Ok(status) => {
-                        eprintln!("Cargo returned with status: {}", status);
-                    }
-                    Err(e) => {
-                        eprintln!("Cargo spawn error: {}", e)
-                    }
-                }
-            }
+        Err(e) => {
+            error!("Failed to parse configuration: {}", e);
+            std::process::exit(1);
         }
-
-        println!("Processing {:?}", &path);
-        let mut file = match File::open(&path) {
-            Ok(file) => file,
-            Err(e) => {
-                error!("Failed to open {:?}: {}", &path, e);
-                return;
+    };
+    let project = project.with_options(BuildOptions {
+        fail_fast: args.fail_fast,
+        jobs: args.jobs,
+        no_gen_cache: args.no_gen_cache,
+    });
+    match command {
+        Command::Build => {
+            if let Err(failures) = project.build() {
+                report_failures(&failures);
+                std::process::exit(1);
             }
-        };
-        let mut template = String::new();
-        if let Err(e) = file.read_to_string(&mut template) {
-            error!("Failed to read template {:?}: {}", &path, e);
-            return;
         }
-        let mut context = ProcessingContext {
-            template_path: &path,
-            template_deps: &mut template_deps,
-            config,
-        };
-        let processed = match process::process(&template, &mut context, &skeleton) {
-            Ok(processed) => processed,
-            Err(e) => {
-                error!("Failed to process template {:?}: {}", &path, e);
-                return;
+        Command::Clean => {
+            if let Err(e) = project.clean() {
+                error!("Failed to clean: {}", e);
+                std::process::exit(1);
             }
-        };
-        let mut file = match File::create(&out_path) {
-            Ok(file) => file,
-            Err(e) => {
-                error!("Failed to open {:?}: {}", &out_path, e);
-                return;
-            }
-        };
-        if let Err(e) = file.write_all(processed.as_bytes()) {
-            error!("Failed to write output {:?}: {}", &out_path, e);
-            return;
         }
-        if stem.to_str().expect("Index doc path is not UTF-8") == config.index {
-            if let Err(e) = std::fs::copy(&out_path, "index.html") {
-                error!("Failed to copy to index.html: {}", e);
-                return;
+        Command::Check => {
+            if let Err(failures) = project.check() {
+                report_failures(&failures);
+                std::process::exit(1);
             }
         }
-    }
-    for en in fs::read_dir(&config.directories.output).unwrap() {
-        let path = en.unwrap().path();
-        if !out_files.contains(&path) {
-            println!("Removing non-generated artifact {:?}", path);
-            fs::remove_file(path).unwrap();
+        Command::New { .. } => unreachable!(),
+        // These only return if they fail
+        Command::Watch => {
+            project.watch(|_| {});
+            std::process::exit(1);
         }
-    }
-    template_deps.save().unwrap();
-}
-
-fn main() {
-    env_logger::init();
-
-    match config::read() {
-        Ok(config) => {
-            util::fs::create_dir_if_not_exists(".noten").unwrap();
-            run(&config);
+        Command::Serve { addr } => {
+            project.serve(&addr);
+            std::process::exit(1);
         }
-        Err(ReadError::Io(err)) => error!(
-            "Failed opening {} ({}). Not a valid noten project.",
-            config::FILENAME,
-            err
-        ),
-        Err(ReadError::De(err)) => error!("Failed to parse {}: {}", config::FILENAME, err),
     }
 }
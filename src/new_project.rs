@@ -0,0 +1,51 @@
+use std::{fs, io, path::Path};
+
+const CONFIG: &str = r#"skeleton = "skeleton.html"
+index = "index"
+
+[directories]
+input = "src"
+output = "out"
+# generators = "generators"
+
+[constants]
+site-name = "My site"
+"#;
+
+const SKELETON: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>%(title)</title>
+%(ifdesc)<meta name="description" content="%(description)">%(endifdesc)
+</head>
+<body>
+%(content)
+</body>
+</html>
+"#;
+
+const INDEX: &str = r#"{
+description = "The front page"
+}
+# Welcome
+
+This is the front page of {{const site-name}}.
+"#;
+
+/// Creates a new project with a minimal configuration, skeleton and index page
+pub fn create(dir: &Path) -> io::Result<()> {
+    let config_path = dir.join(crate::config::FILENAME);
+    if config_path.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{:?} already exists", config_path),
+        ));
+    }
+    fs::create_dir_all(dir.join("src"))?;
+    fs::create_dir_all(dir.join("out"))?;
+    fs::write(config_path, CONFIG)?;
+    fs::write(dir.join("skeleton.html"), SKELETON)?;
+    fs::write(dir.join("src/index.noten"), INDEX)?;
+    Ok(())
+}
@@ -1,35 +1,75 @@
 use {
     crate::{
-        config::Config, skeleton::Skeleton, substitution::substitute, template_deps::TemplateDeps,
+        config::Config,
+        error::{did_you_mean, Diagnostic, Error, Location, Source},
+        generators::Generators,
+        skeleton::{Page, Skeleton},
+        substitution::{find, lookup, substitute},
+        template_deps::TemplateDeps,
+        util::toml::{is_truthy, value_to_string},
     },
     log::debug,
     pulldown_cmark::Options,
     serde_derive::Deserialize,
-    std::{error::Error, path::Path, sync::LazyLock},
+    std::{
+        collections::HashMap,
+        fs,
+        ops::Range,
+        path::{Path, PathBuf},
+        sync::LazyLock,
+    },
 };
 
 #[derive(Default, Deserialize)]
 struct Attributes {
     title: Option<String>,
     description: Option<String>,
+    /// The name of the skeleton to use instead of the default one
+    skeleton: Option<toml::Spanned<String>>,
     constants: Option<toml::value::Table>,
+    /// Every attribute, including the ones above
+    #[serde(skip)]
+    all: toml::value::Table,
 }
 
 /// Reads the optional attribute section at the beginning of the template.
 ///
 /// Returns `Attributes`, and the end position of the attribute section.
-fn read_attributes(input: &str) -> (Attributes, usize) {
-    let first_char = input.chars().next().expect("Couldn't get first character");
-    if first_char != '{' {
-        return (Default::default(), 0);
+fn read_attributes(src: &Source) -> Result<(Attributes, usize), Error> {
+    let input = src.text;
+    if !input.starts_with('{') {
+        return Ok((Default::default(), 0));
     }
-    let closing_brace_pos = input.find('}').expect("Expected closing }");
+    let closing_brace_pos = match input.find('}') {
+        Some(pos) => pos,
+        None => {
+            return Err(Diagnostic::error(
+                Error::Attribute,
+                &src.location(0),
+                "Attribute list without closing `}`",
+                "The attribute list at the beginning of the document must end with `}`",
+            ))
+        }
+    };
     let end = closing_brace_pos + 1;
-    let attribs = toml::from_str(&input[1..closing_brace_pos]).unwrap();
-    (attribs, end)
+    let text = &input[1..closing_brace_pos];
+    let mut attribs: Attributes = toml::from_str(text).map_err(|e| {
+        let span = e.span().unwrap_or(0..1);
+        Diagnostic::error(
+            Error::Attribute,
+            &src.span(1 + span.start, span.len()),
+            format!(
+                "Invalid attribute list: {}",
+                e.message().trim_end().replace('\n', "; ")
+            ),
+            "The attribute list is written in TOML, e.g. `title = \"My page\"`",
+        )
+    })?;
+    attribs.all = toml::from_str(text).expect("The attributes were parsed already");
+    Ok((attribs, end))
 }
 
-fn find_title(input: &str) -> Result<&str, Box<dyn Error>> {
+fn find_title(input: &str) -> Result<&str, String> {
     use regex::Regex;
     static MD: LazyLock<Regex> = LazyLock::new(|| Regex::new("#{1, 9}(.*)").unwrap());
     static HTML: LazyLock<Regex> = LazyLock::new(|| Regex::new("<h[0-9]>(.*)</h[0-9]>").unwrap());
@@ -45,7 +85,7 @@ fn find_title(input: &str) -> Result<&str, Box<dyn Error>> {
             // Try the HTML header
             match HTML.captures(line) {
                 Some(caps) => Ok(caps.get(1).unwrap().as_str().trim()),
-                None => Err(format!("\"{}\" is not a valid header", line).into()),
+                None => Err(format!("\"{}\" is not a valid header", line)),
             }
         }
     }
@@ -69,8 +109,14 @@ fn test_find_title() {
 
 pub struct ProcessingContext<'a> {
     pub template_path: &'a Path,
+    /// Where the page is written
+    pub output_path: &'a Path,
+    pub project_dir: &'a Path,
     pub template_deps: &'a mut TemplateDeps,
     pub config: &'a Config,
+    pub generators: &'a Generators,
+    /// The skeletons pages can select instead of the default one
+    pub skeletons: &'a HashMap<String, Skeleton>,
 }
 
 /// Process a template
@@ -78,42 +124,519 @@ pub fn process(
     input: &str,
     context: &mut ProcessingContext,
     skeleton: &Skeleton,
-) -> Result<String, Box<dyn Error>> {
+) -> Result<String, Error> {
     context.template_deps.clear_deps(context.template_path);
-    let mut output = String::new();
-    let (attribs, mut from) = read_attributes(input);
+    let src = Source {
+        path: context.template_path,
+        text: input,
+    };
+    let (attribs, from) = read_attributes(&src)?;
+    let skeleton = select_skeleton(&src, attribs.skeleton.as_ref(), context.skeletons, skeleton)?;
+    for file in &skeleton.files {
+        context.template_deps.add_dep(context.template_path.to_owned(), file.clone());
+    }
     let title = match attribs.title {
         Some(title) => title,
-        None => find_title(&input[from..])?.to_owned(),
+        None => match find_title(&input[from..]) {
+            Ok(title) => title.to_owned(),
+            Err(msg) => {
+                let body = &input[from..];
+                let first_line = from + body.len() - body.trim_start_matches(['\r', '\n']).len();
+                let line_len = input[first_line..].find('\n').unwrap_or(input.len() - first_line);
+                return Err(Diagnostic::error(
+                    Error::Attribute,
+                    &src.span(first_line, line_len),
+                    format!(
+                        "No title attribute, and couldn't compute the title: {}",
+                        msg
+                    ),
+                    "Start the document with a header (e.g. `# My page`), \
+                     or set the `title` attribute",
+                ));
+            }
+        },
+    };
+    let mut body = Body {
+        title: &title,
+        local_constants: attribs.constants.clone().unwrap_or_default(),
+        loop_vars: Vec::new(),
+        skeleton,
+        output: String::new(),
+        blocks: HashMap::new(),
+        open_block: None,
+        includes: Vec::new(),
     };
-    loop {
-        debug!("Attempting to find next {{{{ or EOF @ {}", from);
-        // Just copy the content as-is until the next {{ or EOF
-        match input[from..].find("{{") {
-            Some(pos) => {
-                debug!("Found {{{{ @ {}", pos);
-                output.push_str(&input[from..from + pos]);
-                let closing_pos = input[from + pos..].find("}}").expect("Expected closing }}");
-                let substitution = &input[from + pos + 2..from + pos + closing_pos];
-                match substitute(substitution, context, attribs.constants.as_ref()) {
-                    Ok(text) => output.push_str(&text),
-                    Err(e) => return Err(format!("Error handling substitution: {}", e).into()),
+    body.expand(&src, from..input.len(), context)?;
+    if let Some((_, loc, _)) = body.open_block {
+        return Err(Diagnostic::error(
+            Error::Substitution,
+            &loc,
+            "block without matching endblock",
+            "Close it with `{{endblock}}`",
+        ));
+    }
+    let Body { output, blocks, .. } = body;
+    let output = markdown_to_html(&output);
+    let blocks = blocks
+        .into_iter()
+        .map(|(name, block)| (name, markdown_to_html(&block)))
+        .collect();
+    skeleton.out(&Page {
+        title: &title,
+        content: &output,
+        description: attribs.description.as_deref(),
+        attributes: &attribs.all,
+        constants: &context.config.constants,
+        blocks: &blocks,
+    })
+}
+
+/// The state of expanding the substitutions in the body of a page
+struct Body<'a> {
+    title: &'a str,
+    /// The constants of the page, and the variables of the loops being expanded
+    local_constants: toml::value::Table,
+    /// The variables of the loops being expanded, innermost last
+    loop_vars: Vec<String>,
+    skeleton: &'a Skeleton,
+    output: String,
+    blocks: HashMap<String, String>,
+    /// The block being read, with where it began, and the content of the page before it
+    open_block: Option<(String, Location, String)>,
+    /// The canonical paths of the partials being included, innermost last
+    includes: Vec<PathBuf>,
+}
+
+impl Body<'_> {
+    /// Expands the substitutions in `range` of `src`, and appends the result to the output
+    fn expand(
+        &mut self,
+        src: &Source,
+        range: Range<usize>,
+        context: &mut ProcessingContext,
+    ) -> Result<(), Error> {
+        let input = &src.text[..range.end];
+        let mut from = range.start;
+        loop {
+            debug!("Attempting to find next {{{{ or EOF @ {}", from);
+            // Just copy the content as-is until the next {{ or EOF
+            let Some(pos) = input[from..].find("{{") else {
+                self.output.push_str(&input[from..]);
+                return Ok(());
+            };
+            debug!("Found {{{{ @ {}", pos);
+            self.output.push_str(&input[from..from + pos]);
+            let closing_pos = match input[from + pos..].find("}}") {
+                Some(pos) => pos,
+                None => {
+                    return Err(Diagnostic::error(
+                        Error::Substitution,
+                        &src.span(from + pos, 2),
+                        "`{{` without matching `}}`",
+                        "Close the substitution with `}}`",
+                    ))
+                }
+            };
+            let substitution = &input[from + pos + 2..from + pos + closing_pos];
+            let loc = src.span(from + pos, closing_pos + 2);
+            let tag_end = from + pos + closing_pos + 2;
+            let command = substitution.trim();
+            let (word, arg) = match command.split_once(char::is_whitespace) {
+                Some((word, arg)) => (word, arg.trim()),
+                None => (command, ""),
+            };
+            debug!("Substitution: \"{}\"", substitution);
+            from = tag_end;
+            match (word, arg) {
+                ("block", name) => {
+                    check_block(
+                        name,
+                        loc.clone(),
+                        &self.open_block,
+                        &self.blocks,
+                        self.skeleton,
+                    )?;
+                    let before = std::mem::take(&mut self.output);
+                    self.open_block = Some((name.to_owned(), loc, before));
+                }
+                ("endblock", "") => {
+                    let Some((name, _, before)) = self.open_block.take() else {
+                        return Err(Diagnostic::error(
+                            Error::Substitution,
+                            &loc,
+                            "endblock without preceding block",
+                            "Remove it, or add a `{{block name}}` before it",
+                        ));
+                    };
+                    let block = std::mem::replace(&mut self.output, before);
+                    self.blocks.insert(name, block);
+                }
+                ("endfor", "") => {
+                    return Err(Diagnostic::error(
+                        Error::Substitution,
+                        &loc,
+                        "endfor without preceding for",
+                        "Remove it, or add a `{{for item in list}}` before it",
+                    ))
                 }
-                debug!("Substitution: \"{}\"", substitution);
-                from = from + pos + closing_pos + 2;
+                ("for", spec) => {
+                    let closing = find_closing(input, tag_end, &loc, "for", "endfor")?;
+                    let body = skip_line_break(input, loc.offset..tag_end)..closing.start;
+                    self.for_loop(spec, loc, src, body, context)?;
+                    from = skip_line_break(input, closing);
+                }
+                ("if", cond) => {
+                    let closing = find_closing(input, tag_end, &loc, "if", "endif")?;
+                    let start = skip_line_break(input, loc.offset..tag_end);
+                    let (then, else_) = match find_else(input, tag_end..closing.start) {
+                        Some(else_) => (
+                            start..else_.start,
+                            skip_line_break(input, else_)..closing.start,
+                        ),
+                        None => (start..closing.start, closing.start..closing.start),
+                    };
+                    let body = if self.condition(cond, &loc, context)? {
+                        then
+                    } else {
+                        else_
+                    };
+                    self.expand(src, body, context)?;
+                    from = skip_line_break(input, closing);
+                }
+                ("else" | "endif", "") => {
+                    return Err(Diagnostic::error(
+                        Error::Substitution,
+                        &loc,
+                        format!("{} without preceding if", word),
+                        "Remove it, or add a `{{if name}}` before it",
+                    ))
+                }
+                ("include", path) => self.include(path, loc, context)?,
+                (name, "") if self.loop_vars.iter().any(|v| is_path_of(name, v)) => {
+                    let value = lookup(name, context.config, Some(&self.local_constants), &loc)?;
+                    self.output.push_str(&value_to_string(value));
+                }
+                _ => self.output.push_str(&substitute(
+                    substitution,
+                    loc.offset + 2,
+                    src,
+                    context,
+                    self.title,
+                    Some(&self.local_constants),
+                )?),
+            }
+        }
+    }
+    /// Expands `body` of `src` for each item of the list in the `for` loop `spec`, which is
+    /// at `loc`
+    fn for_loop(
+        &mut self,
+        spec: &str,
+        loc: Location,
+        src: &Source,
+        body: Range<usize>,
+        context: &mut ProcessingContext,
+    ) -> Result<(), Error> {
+        let (var, list) = match spec.split_whitespace().collect::<Vec<_>>()[..] {
+            [var, "in", list] if !var.contains('.') => (var, list),
+            _ => {
+                return Err(Diagnostic::error(
+                    Error::Substitution,
+                    &loc,
+                    "Invalid for loop",
+                    "Write it like `{{for member in team}}`",
+                ))
+            }
+        };
+        let items: Vec<toml::Value> =
+            match lookup(list, context.config, Some(&self.local_constants), &loc)? {
+                toml::Value::Array(array) => array.clone(),
+                toml::Value::Table(table) => table
+                    .iter()
+                    .map(|(key, value)| {
+                        let entry = [
+                            ("key".into(), key.clone().into()),
+                            ("value".into(), value.clone()),
+                        ];
+                        toml::Value::Table(entry.into_iter().collect())
+                    })
+                    .collect(),
+                _ => {
+                    return Err(Diagnostic::error(
+                        Error::Substitution,
+                        &loc,
+                        format!("`{}` is neither an array nor a table", list),
+                        "Only arrays and tables can be looped over",
+                    ))
+                }
+            };
+        // The loop variable shadows the constant of the same name
+        let shadowed = self.local_constants.remove(var);
+        self.loop_vars.push(var.to_owned());
+        for item in items {
+            self.local_constants.insert(var.to_owned(), item);
+            self.expand(src, body.clone(), context)?;
+        }
+        self.loop_vars.pop();
+        match shadowed {
+            Some(value) => self.local_constants.insert(var.to_owned(), value),
+            None => self.local_constants.remove(var),
+        };
+        Ok(())
+    }
+    /// Evaluates the condition `cond` of the `if` at `loc`.
+    ///
+    /// It's either a constant, which is tested for truthiness, or a comparison of a constant
+    /// with a literal, like `season == "summer"`. Constants that don't exist are false.
+    fn condition(
+        &self,
+        cond: &str,
+        loc: &Location,
+        context: &ProcessingContext,
+    ) -> Result<bool, Error> {
+        // Names can't contain `=` or `!`, so the first one starts the operator
+        let (name, comparison) = match cond.find(['=', '!']) {
+            Some(pos) => (cond[..pos].trim(), Some(&cond[pos..])),
+            None => (cond, None),
+        };
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(Diagnostic::error(
+                Error::Substitution,
+                loc,
+                format!("Invalid condition `{}`", cond),
+                "Write it like `{{if name}}` or `{{if name == \"value\"}}`",
+            ));
+        }
+        // A constant or field that doesn't exist is false, like in `%(if)` of skeletons
+        let value = find(name, context.config, Some(&self.local_constants));
+        let Some(comparison) = comparison else {
+            return Ok(value.is_some_and(is_truthy));
+        };
+        let (equal, literal) = match comparison.split_at(comparison.len().min(2)) {
+            ("==", literal) => (true, literal.trim()),
+            ("!=", literal) => (false, literal.trim()),
+            _ => {
+                return Err(Diagnostic::error(
+                    Error::Substitution,
+                    loc,
+                    format!("Invalid condition `{}`", cond),
+                    "Only `==` and `!=` comparisons are supported",
+                ))
             }
-            None => {
-                output.push_str(&input[from..]);
-                break;
+        };
+        let parsed: Result<toml::value::Table, _> = toml::from_str(&format!("v = {}", literal));
+        let Some(literal) = parsed.ok().and_then(|mut t| t.remove("v")) else {
+            return Err(Diagnostic::error(
+                Error::Substitution,
+                loc,
+                format!("Invalid literal `{}`", literal),
+                "Literals are written like in TOML, e.g. `\"summer\"`, `3` or `true`",
+            ));
+        };
+        Ok((value == Some(&literal)) == equal)
+    }
+    /// Expands the partial at `rel_path` in the partials directory, which is included at `loc`
+    fn include(
+        &mut self,
+        rel_path: &str,
+        loc: Location,
+        context: &mut ProcessingContext,
+    ) -> Result<(), Error> {
+        if rel_path.is_empty() {
+            return Err(Diagnostic::error(
+                Error::Substitution,
+                &loc,
+                "`include` needs the path of a partial",
+                "Write it like `{{include contact.noten}}`",
+            ));
+        }
+        let partials = &context.config.directories.partials;
+        let path = partials.join(rel_path);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) => {
+                return Err(Diagnostic::error(
+                    Error::Substitution,
+                    &loc,
+                    format!("Failed to read partial `{}`: {}", rel_path, e),
+                    format!("Partials are looked up in {:?}", partials),
+                ))
             }
+        };
+        let canonical = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+        let in_cycle = self.includes.contains(&canonical)
+            || fs::canonicalize(context.template_path).is_ok_and(|t| t == canonical);
+        if in_cycle {
+            return Err(Diagnostic::error(
+                Error::Substitution,
+                &loc,
+                format!("`{}` includes itself", rel_path),
+                "Partials can't include each other in a circle",
+            ));
         }
+        context.template_deps.add_dep(context.template_path.to_owned(), path.clone());
+        self.includes.push(canonical);
+        let src = Source {
+            path: &path,
+            text: &text,
+        };
+        self.expand(&src, 0..text.len(), context)?;
+        self.includes.pop();
+        Ok(())
+    }
+}
+
+/// Finds the `{{closing}}` tag in `input` that ends the `{{opening}}` tag at `loc`, which
+/// ends at `from`.
+///
+/// Returns the range of the closing tag.
+fn find_closing(
+    input: &str,
+    from: usize,
+    loc: &Location,
+    opening: &str,
+    closing: &str,
+) -> Result<Range<usize>, Error> {
+    // How many tags of the same kind are open inside the one we are looking for
+    let mut depth = 0;
+    for (tag, word) in tags(input, from) {
+        if word == opening {
+            depth += 1;
+        } else if word == closing {
+            if depth == 0 {
+                return Ok(tag);
+            }
+            depth -= 1;
+        }
+    }
+    Err(Diagnostic::error(
+        Error::Substitution,
+        loc,
+        format!("{} without matching {}", opening, closing),
+        format!("Close it with `{{{{{}}}}}`", closing),
+    ))
+}
+
+/// Finds the `{{else}}` tag in `body` of `input` that belongs to the `{{if}}` right before it
+fn find_else(input: &str, body: Range<usize>) -> Option<Range<usize>> {
+    let mut depth = 0;
+    for (tag, word) in tags(&input[..body.end], body.start) {
+        match word {
+            "if" => depth += 1,
+            "endif" => depth -= 1,
+            "else" if depth == 0 => return Some(tag),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The `{{…}}` tags in `input` after `from`, with their ranges and first words
+fn tags(input: &str, mut from: usize) -> impl Iterator<Item = (Range<usize>, &str)> {
+    std::iter::from_fn(move || {
+        let start = from + input[from..].find("{{")?;
+        let len = input[start..].find("}}")?;
+        from = start + len + 2;
+        let word = input[start + 2..start + len].split_whitespace().next();
+        Some((start..from, word.unwrap_or_default()))
+    })
+}
+
+/// Returns the end of `tag` in `input`, including the line break after it if the tag
+/// starts a line.
+///
+/// Used for tags that enclose others, so that they don't leave empty lines behind.
+fn skip_line_break(input: &str, tag: Range<usize>) -> usize {
+    if !(tag.start == 0 || input[..tag.start].ends_with('\n')) {
+        return tag.end;
+    }
+    let rest = &input[tag.end..];
+    match rest.strip_prefix("\r\n").or_else(|| rest.strip_prefix('\n')) {
+        Some(after) => input.len() - after.len(),
+        None => tag.end,
+    }
+}
+
+/// Whether `path` is `name`, or a field of it, like `name.field`
+fn is_path_of(path: &str, name: &str) -> bool {
+    path.split('.').next() == Some(name)
+}
+
+fn markdown_to_html(markdown: &str) -> String {
+    let parser = pulldown_cmark::Parser::new_ext(markdown, Options::ENABLE_TABLES);
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, parser);
+    html
+}
+
+/// The skeleton the page selected with the `skeleton` attribute, or `default`
+fn select_skeleton<'a>(
+    src: &Source,
+    name: Option<&toml::Spanned<String>>,
+    skeletons: &'a HashMap<String, Skeleton>,
+    default: &'a Skeleton,
+) -> Result<&'a Skeleton, Error> {
+    let Some(name) = name else {
+        return Ok(default);
+    };
+    if let Some(skeleton) = skeletons.get(name.get_ref()) {
+        return Ok(skeleton);
+    }
+    let hint =
+        did_you_mean(name.get_ref(), skeletons.keys().map(|k| &k[..])).unwrap_or_else(|| {
+            "Skeletons are defined in the `[skeletons]` section of the configuration".into()
+        });
+    let span = name.span();
+    Err(Diagnostic::error(
+        Error::Attribute,
+        &src.span(1 + span.start, span.len()),
+        format!("Skeleton `{}` does not exist", name.get_ref()),
+        hint,
+    ))
+}
+
+/// Checks that the page can begin the block `name` at `loc`
+fn check_block(
+    name: &str,
+    loc: Location,
+    open_block: &Option<(String, Location, String)>,
+    blocks: &HashMap<String, String>,
+    skeleton: &Skeleton,
+) -> Result<(), Error> {
+    if name.is_empty() || name.contains(char::is_whitespace) {
+        return Err(Diagnostic::error(
+            Error::Substitution,
+            &loc,
+            "`block` needs a name",
+            "Write it like `{{block sidebar}}`",
+        ));
+    }
+    if open_block.is_some() {
+        return Err(Diagnostic::error(
+            Error::Substitution,
+            &loc,
+            "Blocks can't be nested",
+            "Close the previous block with `{{endblock}}` first",
+        ));
+    }
+    if blocks.contains_key(name) {
+        return Err(Diagnostic::error(
+            Error::Substitution,
+            &loc,
+            format!("Block `{}` is given twice", name),
+            "Remove one of them",
+        ));
+    }
+    let names = skeleton.block_names();
+    if !names.contains(&name) {
+        let hint = did_you_mean(name, names)
+            .unwrap_or_else(|| "Blocks are defined in the skeleton with `%(block name)`".into());
+        return Err(Diagnostic::error(
+            Error::Substitution,
+            &loc,
+            format!("The skeleton has no block `{}`", name),
+            hint,
+        ));
     }
-    let parser = pulldown_cmark::Parser::new_ext(&output, Options::ENABLE_TABLES);
-    let mut output = String::new();
-    pulldown_cmark::html::push_html(&mut output, parser);
-    skeleton.out(
-        &title,
-        &output,
-        attribs.description.as_ref().map(|s| &s[..]),
-    )
+    Ok(())
 }
@@ -0,0 +1,563 @@
+use {
+    crate::{
+        build_state::{self, BuildState},
+        config::{self, Config, Overrides},
+        error::Error,
+        generators::{self, BuildFailure, Generators},
+        process::{self, ProcessingContext},
+        skeleton::Skeletons,
+        template_deps::{self, TemplateDeps},
+        util,
+    },
+    log::{debug, error, info, warn},
+    std::{
+        fs,
+        num::NonZeroUsize,
+        path::{Path, PathBuf},
+        sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+        time::SystemTime,
+    },
+};
+
+/// Options that affect how the pages are built
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BuildOptions {
+    /// Stop at the first error, instead of continuing with the rest of the files
+    pub fail_fast: bool,
+    /// How many pages to render in parallel. Defaults to the number of available CPUs.
+    pub jobs: Option<NonZeroUsize>,
+    /// Always run the generators, instead of reusing their cached outputs
+    pub no_gen_cache: bool,
+}
+
+impl BuildOptions {
+    /// The number of worker threads to render pages on
+    fn jobs(&self) -> usize {
+        self.jobs
+            .or_else(|| std::thread::available_parallelism().ok())
+            .map_or(1, NonZeroUsize::get)
+    }
+}
+
+/// A page that is out of date, and needs to be rendered
+struct Page {
+    path: PathBuf,
+    out_path: PathBuf,
+    template: String,
+    is_index: bool,
+    /// When the build of this page started, which is recorded in the build state
+    build_start: SystemTime,
+}
+
+/// Which pages [`Project::run`] should consider for rebuilding
+pub(crate) enum Scope<'a> {
+    /// Every page that is out of date
+    All,
+    /// Only these templates, given as canonical paths, if they are out of date.
+    ///
+    /// Assets are always copied if they changed, and stale outputs are always removed.
+    Only(&'a [PathBuf]),
+}
+
+/// A noten project: a configuration, and the directory its paths are relative to
+pub struct Project {
+    root: PathBuf,
+    /// The paths in here are already resolved against `root`
+    config: Config,
+    options: BuildOptions,
+}
+
+/// Logs `err`, and adds it to `failures`
+fn log_failure(failures: &mut Vec<Error>, what: &str, err: Error) {
+    error!("{}: {}", what, err);
+    failures.push(err);
+}
+
+/// Logs a summary of the errors that occurred during a build
+pub fn report_failures(failures: &[Error]) {
+    if failures.is_empty() {
+        return;
+    }
+    error!("{} error(s) occurred:", failures.len());
+    for e in failures {
+        error!("  {}", e.summary());
+    }
+}
+
+impl Project {
+    /// Opens the project in `root`, using the configuration file at `config_path`
+    /// (relative to `root`).
+    pub fn open(
+        root: impl Into<PathBuf>,
+        config_path: impl AsRef<Path>,
+        overrides: &Overrides,
+    ) -> Result<Self, Error> {
+        let root = root.into();
+        let config = config::read(&root.join(config_path), overrides)?;
+        Ok(Self::new(root, config))
+    }
+    /// Creates a project from a configuration whose paths are relative to `root`
+    pub fn new(root: impl Into<PathBuf>, mut config: Config) -> Self {
+        let root = root.into();
+        config.resolve(&root);
+        Self {
+            root,
+            config,
+            options: BuildOptions::default(),
+        }
+    }
+    pub fn with_options(mut self, options: BuildOptions) -> Self {
+        self.options = options;
+        self
+    }
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+    /// The configuration, with its paths resolved against [`Project::root`]
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+    /// Rereads the configuration file
+    pub fn reload(&mut self) -> Result<(), Error> {
+        let mut config = config::read(&self.config.path, &self.config.overrides)?;
+        config.resolve(&self.root);
+        self.config = config;
+        Ok(())
+    }
+    /// Builds the pages that are out of date, and copies the assets.
+    ///
+    /// Returns the errors that occurred. They are also logged as they happen.
+    pub fn build(&self) -> Result<(), Vec<Error>> {
+        let failures = self.run(Scope::All);
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(failures)
+        }
+    }
+    /// The path of the copy of the index document
+    fn index_path(&self) -> PathBuf {
+        self.root.join("index.html")
+    }
+    pub(crate) fn template_deps_path(&self) -> PathBuf {
+        self.root.join(template_deps::PATH)
+    }
+    fn build_state_path(&self) -> PathBuf {
+        self.root.join(build_state::PATH)
+    }
+    /// The files in the input directory that are pages or assets.
+    ///
+    /// What noten itself reads and writes is left out, in case it's in the input directory,
+    /// e.g. with `input = "."`. Partials are only used by including them.
+    fn input_files(&self) -> std::io::Result<Vec<PathBuf>> {
+        let config = &self.config;
+        let dirs = &config.directories;
+        let skeletons = [&config.skeleton].into_iter().chain(config.skeletons.values());
+        let skip: Vec<PathBuf> = [&dirs.output, &dirs.partials, &self.root.join(".noten")]
+            .into_iter()
+            .chain([&config.path, &self.index_path()])
+            .chain(skeletons)
+            .filter_map(|path| fs::canonicalize(path).ok())
+            .collect();
+        util::fs::walk_files_except(&dirs.input, &skip)
+    }
+    /// Where the page built from the template at `rel_path` (relative to the input directory)
+    /// is written
+    fn page_output_path(&self, rel_path: &Path) -> PathBuf {
+        self.config.directories.output.join(rel_path).with_extension("html")
+    }
+    /// The registry of generators for a run
+    pub(crate) fn generators(&self) -> Generators {
+        let cache_dir = self.root.join(generators::CACHE_PATH);
+        Generators::new(&self.root, cache_dir, !self.options.no_gen_cache)
+    }
+    /// Builds the pages in `scope` that are out of date, and copies the assets.
+    ///
+    /// Returns the errors that occurred. They are also logged as they happen.
+    pub(crate) fn run(&self, scope: Scope) -> Vec<Error> {
+        let config = &self.config;
+        let mut failures = Vec::new();
+        if let Err(e) = util::fs::create_dir_if_not_exists(self.root.join(".noten")) {
+            let e = Error::Io(self.root.join(".noten"), e);
+            log_failure(&mut failures, "Failed to create state directory", e);
+            return failures;
+        }
+        let skeletons = match Skeletons::load(config) {
+            Ok(skeletons) => skeletons,
+            Err(e) => {
+                log_failure(&mut failures, "Failed to parse skeleton", e);
+                return failures;
+            }
+        };
+
+        let template_deps_path = self.template_deps_path();
+        // Without the dependencies, we can't tell which pages are out of date
+        let mut deps_lost = false;
+        let mut template_deps = match TemplateDeps::open(&template_deps_path) {
+            Ok(deps) => deps,
+            Err(e) => {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    warn!(
+                        "Failed to load template dependencies, rebuilding everything: {}",
+                        e
+                    );
+                    deps_lost = true;
+                }
+                TemplateDeps::default()
+            }
+        };
+
+        let input_dir: &Path = &config.directories.input;
+        let output_dir: &Path = &config.directories.output;
+        let entries = match self.input_files() {
+            Ok(entries) => entries,
+            Err(e) => {
+                let e = Error::Io(input_dir.to_owned(), e);
+                log_failure(&mut failures, "Failed to read input directory", e);
+                return failures;
+            }
+        };
+        let build_state_path = self.build_state_path();
+        let mut build_state = match BuildState::open(&build_state_path) {
+            Ok(_) if deps_lost => BuildState::default(),
+            Ok(state) => state,
+            Err(e) => {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    warn!("Failed to load build state, rebuilding everything: {}", e);
+                }
+                BuildState::default()
+            }
+        };
+        let index_path = self.index_path();
+        let mut out_files = Vec::new();
+        let mut templates = Vec::new();
+        let mut pages = Vec::new();
+        let generators = self.generators();
+        let mut aborted = false;
+        // Records the failure, then moves on to the next file, or stops if failing fast
+        macro_rules! fail {
+            ($what:expr, $err:expr) => {{
+                log_failure(&mut failures, $what, $err);
+                if self.options.fail_fast {
+                    aborted = true;
+                    break;
+                }
+                continue;
+            }};
+        }
+        for path in entries {
+            let rel_path =
+                path.strip_prefix(input_dir).expect("Entry is not under the input directory");
+            let is_template = path.extension() == Some("noten".as_ref());
+            if !is_template && !config.assets.is_asset(rel_path) {
+                warn!(
+                    "Skipping {:?}, because it's neither a template nor an asset",
+                    path
+                );
+                continue;
+            }
+            let out_path = if is_template {
+                self.page_output_path(rel_path)
+            } else {
+                output_dir.join(rel_path)
+            };
+            out_files.push(out_path.clone());
+            if let Some(parent) = out_path.parent() {
+                if let Err(e) = fs::create_dir_all(parent) {
+                    fail!(
+                        "Failed to create output directory",
+                        Error::Io(parent.to_owned(), e)
+                    );
+                }
+            }
+            if !is_template {
+                if !util::fs::is_newer(&path, &out_path) {
+                    continue;
+                }
+                if let Err(e) = fs::copy(&path, &out_path) {
+                    fail!("Failed to copy asset", Error::Io(path.clone(), e));
+                }
+                continue;
+            }
+            templates.push(path.clone());
+            if let Scope::Only(paths) = scope {
+                if !fs::canonicalize(&path).is_ok_and(|path| paths.contains(&path)) {
+                    continue;
+                }
+            }
+            // Rebuild the generators the page uses, so that it's out of date if they changed
+            let deps = template_deps.hash_map.get(&path).map_or(&[][..], |deps| &deps[..]);
+            let failure = generators.used_by(deps, config).iter().find_map(|name| {
+                match generators.build(name, config) {
+                    // Processing the page reports this, pointing at where the generator is used
+                    Ok(_) | Err(BuildFailure::Invalid(_)) => None,
+                    Err(failure) => Some(failure),
+                }
+            });
+            match failure {
+                None | Some(BuildFailure::Invalid(_)) => {}
+                Some(BuildFailure::New(e)) => fail!("Failed to build generator", e),
+                Some(BuildFailure::Reported) => {
+                    debug!(
+                        "Skipping {:?}, because a generator it uses failed to build",
+                        &path
+                    );
+                    continue;
+                }
+            }
+
+            let build_start = SystemTime::now();
+            let template = match fs::read_to_string(&path) {
+                Ok(template) => template,
+                Err(e) => fail!("Failed to read template", Error::Io(path.clone(), e)),
+            };
+            // The skeleton the page uses is among its dependencies
+            let mut inputs = vec![config.path.as_path()];
+            if let Some(deps) = template_deps.hash_map.get(&path) {
+                inputs.extend(deps.iter().map(|p| p.as_path()));
+            }
+            let is_index = rel_path.with_extension("") == Path::new(&config.index);
+            if !build_state.is_outdated(&path, &template, &out_path, &inputs) {
+                debug!("{:?} is up to date", &path);
+                if is_index && !index_path.exists() {
+                    if let Err(e) = copy_index(&out_path, &index_path) {
+                        fail!("Failed to copy to index.html", e);
+                    }
+                }
+                continue;
+            }
+            pages.push(Page {
+                path,
+                out_path,
+                template,
+                is_index,
+                build_start,
+            });
+        }
+        if !aborted {
+            let rendered = self.render_pages(&pages, &skeletons, &generators);
+            // Every page ran all its generators, so the cache entries nothing used are stale
+            let rendered_all = rendered.len() == templates.len()
+                && rendered.iter().all(|(_, _, result)| result.is_ok());
+            if matches!(scope, Scope::All) && rendered_all {
+                if let Err(e) = generators.prune() {
+                    warn!("Failed to remove unused generator outputs: {}", e);
+                }
+            }
+            for (i, deps, result) in rendered {
+                let page = &pages[i];
+                template_deps.replace(&page.path, deps);
+                if let Err(e) = result {
+                    log_failure(&mut failures, "Failed to process template", e);
+                    continue;
+                }
+                build_state.record(&page.path, &page.template, page.build_start);
+                if page.is_index {
+                    if let Err(e) = copy_index(&page.out_path, &index_path) {
+                        log_failure(&mut failures, "Failed to copy to index.html", e);
+                    }
+                }
+            }
+        }
+        // If we stopped early, we don't know the full set of outputs
+        if !aborted {
+            if let Err(e) = remove_stale_outputs(output_dir, &out_files) {
+                log_failure(&mut failures, "Failed to remove stale outputs", e);
+            }
+        }
+        if let Err(e) = template_deps.save(&template_deps_path) {
+            log_failure(
+                &mut failures,
+                "Failed to save template dependencies",
+                Error::Io(template_deps_path, e),
+            );
+        }
+        if !aborted {
+            build_state.retain(&templates.iter().map(|p| p.as_path()).collect::<Vec<_>>());
+        }
+        if let Err(e) = build_state.save(&build_state_path) {
+            log_failure(
+                &mut failures,
+                "Failed to save build state",
+                Error::Io(build_state_path, e),
+            );
+        }
+        failures
+    }
+    /// Renders `pages` on a pool of worker threads, and writes their outputs.
+    ///
+    /// Each page records its dependencies into its own [`TemplateDeps`], which is returned
+    /// along with the index of the page and the result, in the order of `pages`.
+    /// When failing fast, pages that weren't started yet when a failure occurred are skipped.
+    fn render_pages(
+        &self,
+        pages: &[Page],
+        skeletons: &Skeletons,
+        generators: &Generators,
+    ) -> Vec<(usize, TemplateDeps, Result<(), Error>)> {
+        let next = AtomicUsize::new(0);
+        let stop = AtomicBool::new(false);
+        let worker = || {
+            let mut rendered = Vec::new();
+            while !stop.load(Ordering::Relaxed) {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(page) = pages.get(i) else {
+                    break;
+                };
+                let mut deps = TemplateDeps::default();
+                let result = self.render_page(page, skeletons, &mut deps, generators);
+                if result.is_err() && self.options.fail_fast {
+                    stop.store(true, Ordering::Relaxed);
+                }
+                rendered.push((i, deps, result));
+            }
+            rendered
+        };
+        let workers = self.options.jobs().min(pages.len()).max(1);
+        debug!("Rendering {} page(s) on {} thread(s)", pages.len(), workers);
+        let mut rendered: Vec<_> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..workers).map(|_| scope.spawn(worker)).collect();
+            handles.into_iter().flat_map(|h| h.join().unwrap()).collect()
+        });
+        rendered.sort_by_key(|(i, _, _)| *i);
+        rendered
+    }
+    /// Processes a page, and writes its output
+    fn render_page(
+        &self,
+        page: &Page,
+        skeletons: &Skeletons,
+        template_deps: &mut TemplateDeps,
+        generators: &Generators,
+    ) -> Result<(), Error> {
+        info!("Processing {:?}", &page.path);
+        let mut context = ProcessingContext {
+            template_path: &page.path,
+            output_path: &page.out_path,
+            project_dir: &self.root,
+            template_deps,
+            config: &self.config,
+            generators,
+            skeletons: &skeletons.named,
+        };
+        let processed = process::process(&page.template, &mut context, &skeletons.default)?;
+        fs::write(&page.out_path, processed).map_err(|e| Error::Io(page.out_path.clone(), e))
+    }
+    /// Processes every template without writing any output.
+    ///
+    /// Returns the errors that occurred. They are also logged as they happen.
+    pub fn check(&self) -> Result<(), Vec<Error>> {
+        let config = &self.config;
+        let mut failures = Vec::new();
+        let skeletons = match Skeletons::load(config) {
+            Ok(skeletons) => skeletons,
+            Err(e) => {
+                log_failure(&mut failures, "Failed to parse skeleton", e);
+                return Err(failures);
+            }
+        };
+        let entries = match self.input_files() {
+            Ok(entries) => entries,
+            Err(e) => {
+                let e = Error::Io(config.directories.input.clone(), e);
+                log_failure(&mut failures, "Failed to read input directory", e);
+                return Err(failures);
+            }
+        };
+        // Dependencies found during checking are not recorded
+        let mut template_deps = TemplateDeps::default();
+        let generators = self.generators();
+        for path in entries {
+            if path.extension() != Some("noten".as_ref()) {
+                continue;
+            }
+            info!("Checking {:?}", &path);
+            let template = match fs::read_to_string(&path) {
+                Ok(template) => template,
+                Err(e) => {
+                    log_failure(&mut failures, "Failed to read template", Error::Io(path, e));
+                    if self.options.fail_fast {
+                        break;
+                    }
+                    continue;
+                }
+            };
+            let rel_path = path.strip_prefix(&config.directories.input).unwrap_or(&path);
+            let output_path = self.page_output_path(rel_path);
+            let mut context = ProcessingContext {
+                template_path: &path,
+                output_path: &output_path,
+                project_dir: &self.root,
+                template_deps: &mut template_deps,
+                config,
+                generators: &generators,
+                skeletons: &skeletons.named,
+            };
+            if let Err(e) = process::process(&template, &mut context, &skeletons.default) {
+                log_failure(&mut failures, "Failed to process template", e);
+                if self.options.fail_fast {
+                    break;
+                }
+            }
+        }
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(failures)
+        }
+    }
+    /// Removes the output directory, the build state, and the copy of the index document
+    pub fn clean(&self) -> Result<(), Error> {
+        for dir in [&self.config.directories.output, &self.root.join(".noten")] {
+            if dir.exists() {
+                info!("Removing {:?}", dir);
+                fs::remove_dir_all(dir).map_err(|e| Error::Io(dir.clone(), e))?;
+            }
+        }
+        let index_path = self.index_path();
+        if index_path.exists() {
+            info!("Removing {:?}", index_path);
+            fs::remove_file(&index_path).map_err(|e| Error::Io(index_path, e))?;
+        }
+        Ok(())
+    }
+    /// Builds the project, then keeps rebuilding the affected pages whenever something changes.
+    ///
+    /// `on_rebuild` is called with the project after each rebuild. Its configuration may have
+    /// been reloaded since the previous call.
+    ///
+    /// Only returns if watching fails.
+    pub fn watch(self, on_rebuild: impl FnMut(&Project)) {
+        crate::watch::watch(self, on_rebuild)
+    }
+    /// Serves the output at `addr`, while rebuilding and live reloading on changes.
+    ///
+    /// Only returns if listening or watching fails.
+    pub fn serve(self, addr: &str) {
+        crate::serve::serve(self, addr)
+    }
+}
+
+/// Removes every file in `output_dir` that is not in `out_files`, then the empty directories
+fn remove_stale_outputs(output_dir: &Path, out_files: &[PathBuf]) -> Result<(), Error> {
+    let io_err = |e| Error::Io(output_dir.to_owned(), e);
+    let outputs = match util::fs::walk_files(output_dir) {
+        Ok(outputs) => outputs,
+        // Nothing was written yet, so there is nothing to clean up
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(io_err(e)),
+    };
+    for path in outputs {
+        if !out_files.contains(&path) {
+            info!("Removing non-generated artifact {:?}", path);
+            fs::remove_file(&path).map_err(|e| Error::Io(path, e))?;
+        }
+    }
+    util::fs::remove_empty_dirs(output_dir).map_err(io_err)
+}
+
+/// Copies the index document to `index_path`
+fn copy_index(out_path: &Path, index_path: &Path) -> Result<(), Error> {
+    fs::copy(out_path, index_path).map_err(|e| Error::Io(out_path.to_owned(), e))?;
+    Ok(())
+}
@@ -0,0 +1,199 @@
+use {
+    crate::{project::Project, watch},
+    log::{debug, error, info},
+    std::{
+        fs,
+        path::{Component, Path, PathBuf},
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc, Mutex,
+        },
+    },
+    tiny_http::{Header, Request, Response, Server},
+};
+
+pub const DEFAULT_ADDR: &str = "127.0.0.1:8000";
+
+/// Endpoint that returns the current build version. Polled by the live reload script.
+const VERSION_ENDPOINT: &str = "/__noten/version";
+
+/// Injected into served HTML pages. Reloads the page when the build version changes.
+///
+/// `{VERSION}` is replaced with the build version at the time the page was served.
+const LIVE_RELOAD_SCRIPT: &str = r#"<script>
+(function () {
+    const version = "{VERSION}";
+    setInterval(async function () {
+        try {
+            const res = await fetch("/__noten/version", { cache: "no-store" });
+            if ((await res.text()) !== version) {
+                location.reload();
+            }
+        } catch (e) {}
+    }, 500);
+})();
+</script>
+"#;
+
+/// What is served, which changes when the configuration is reloaded
+#[derive(Clone)]
+struct Site {
+    output_dir: PathBuf,
+    /// The file name of the index page
+    index: String,
+}
+
+impl Site {
+    fn new(project: &Project) -> Self {
+        Self {
+            output_dir: project.config().directories.output.clone(),
+            index: format!("{}.html", project.config().index),
+        }
+    }
+}
+
+/// Serves the output directory, while rebuilding and live reloading on changes.
+///
+/// Stops when watching for changes fails, rather than serving pages that are no longer rebuilt.
+pub(crate) fn serve(project: Project, addr: &str) {
+    let server = match Server::http(addr) {
+        Ok(server) => Arc::new(server),
+        Err(e) => {
+            error!("Failed to listen on {}: {}", addr, e);
+            return;
+        }
+    };
+    let site = Site::new(&project);
+    info!("Serving {:?} at http://{}", site.output_dir, addr);
+    let site = Arc::new(Mutex::new(site));
+    let version = Arc::new(AtomicU64::new(0));
+    std::thread::spawn({
+        let (site, version, server) =
+            (Arc::clone(&site), Arc::clone(&version), Arc::clone(&server));
+        move || {
+            watch::watch(project, |project| {
+                let new = Site::new(project);
+                let mut site = site.lock().unwrap();
+                if new.output_dir != site.output_dir {
+                    info!("Serving {:?} instead", new.output_dir);
+                }
+                *site = new;
+                version.fetch_add(1, Ordering::SeqCst);
+            });
+            error!("Stopped watching for changes, so the server is stopped too");
+            server.unblock();
+        }
+    });
+    for request in server.incoming_requests() {
+        let version = version.load(Ordering::SeqCst);
+        let site = site.lock().unwrap().clone();
+        if let Err(e) = respond(request, &site.output_dir, &site.index, version) {
+            error!("Failed to respond to request: {}", e);
+        }
+    }
+}
+
+fn respond(request: Request, output_dir: &Path, index: &str, version: u64) -> std::io::Result<()> {
+    let url = request.url().split(['?', '#']).next().unwrap_or_default();
+    debug!("{} {}", request.method(), url);
+    if url == VERSION_ENDPOINT {
+        let response = Response::from_string(version.to_string())
+            .with_header(header("Cache-Control", "no-store"));
+        return request.respond(response);
+    }
+    let Some(path) = resolve(output_dir, index, &percent_decode(url)) else {
+        return request.respond(Response::from_string("404 Not Found").with_status_code(404));
+    };
+    let mut data = fs::read(&path)?;
+    let content_type = content_type(&path);
+    if content_type.starts_with("text/html") {
+        data = inject_script(data, version);
+    }
+    let response = Response::from_data(data)
+        .with_header(header("Content-Type", content_type))
+        .with_header(header("Cache-Control", "no-store"));
+    request.respond(response)
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name, value).expect("Invalid header")
+}
+
+/// Finds the file in `output_dir` that corresponds to the url path `url`
+fn resolve(output_dir: &Path, index: &str, url: &str) -> Option<PathBuf> {
+    let rel = Path::new(url.trim_start_matches('/'));
+    // Don't allow escaping the output directory
+    if !rel.components().all(|c| matches!(c, Component::Normal(_))) {
+        return None;
+    }
+    let path = output_dir.join(rel);
+    let candidates = if url == "/" {
+        vec![path.join("index.html"), output_dir.join(index)]
+    } else if path.is_dir() {
+        vec![path.join("index.html")]
+    } else {
+        // Allow leaving off the .html extension
+        vec![path.clone(), path.with_extension("html")]
+    };
+    candidates.into_iter().find(|p| p.is_file())
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            if let Some(b) = text.get(i + 1..i + 3).and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                out.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn content_type(path: &Path) -> &'static str {
+    let ext = path.extension().and_then(|ext| ext.to_str()).unwrap_or_default();
+    match &ext.to_ascii_lowercase()[..] {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "pdf" => "application/pdf",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Inserts the live reload script before `</body>`, or at the end if there is none
+fn inject_script(mut html: Vec<u8>, version: u64) -> Vec<u8> {
+    let script = LIVE_RELOAD_SCRIPT.replace("{VERSION}", &version.to_string());
+    let pos = html
+        .windows(7)
+        .rposition(|w| w.eq_ignore_ascii_case(b"</body>"))
+        .unwrap_or(html.len());
+    html.splice(pos..pos, script.into_bytes());
+    html
+}
+
+#[test]
+fn test_inject_script() {
+    let html = inject_script(b"<html><body>Hi</BODY></html>".to_vec(), 3);
+    let html = String::from_utf8(html).unwrap();
+    assert!(html.starts_with("<html><body>Hi<script>"));
+    assert!(html.ends_with("</script>\n</BODY></html>"));
+    assert!(html.contains(r#"const version = "3";"#));
+}
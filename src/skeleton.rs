@@ -1,151 +1,869 @@
 use {
+    crate::{
+        config::Config,
+        error::{did_you_mean, list, Diagnostic, Error, Location, Source},
+        util::toml::{is_truthy, value_to_string},
+    },
     log::debug,
-    std::{error::Error, fs::File, io::Read as _, path::Path},
+    std::{
+        collections::HashMap,
+        fs,
+        ops::Range,
+        path::{Path, PathBuf},
+        slice,
+    },
 };
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 enum Segment {
+    /// A region that skeletons extending this one, and pages, can replace
+    Block {
+        name: String,
+        loc: Location,
+        body: Vec<Segment>,
+    },
     Content,
-    Description,
-    IfDesc(Vec<Segment>),
+    /// The location is used for reporting a missing description
+    Description(Location),
+    If {
+        name: String,
+        then: Vec<Segment>,
+        else_: Vec<Segment>,
+    },
+    /// A partial, which is replaced by its segments when the skeleton is loaded
+    Include {
+        path: String,
+        loc: Location,
+    },
     Text(String),
     Title,
+    /// An attribute or a constant
+    Value {
+        name: String,
+        loc: Location,
+    },
 }
 
+/// The name of the skeleton another one extends, and where it says so
+type Extends = (String, Location);
+
+#[derive(Clone)]
 pub struct Skeleton {
+    /// The files the skeleton was read from, including the ones it extends
+    pub(crate) files: Vec<PathBuf>,
+    extends: Option<Extends>,
     segments: Vec<Segment>,
 }
 
+/// The skeletons of a project
+pub(crate) struct Skeletons {
+    pub default: Skeleton,
+    /// The skeletons in `[skeletons]`, which pages can select with the `skeleton` attribute
+    pub named: HashMap<String, Skeleton>,
+}
+
+impl Skeletons {
+    /// Parses every skeleton in `config`
+    pub fn load(config: &Config) -> Result<Self, Error> {
+        let partials = &config.directories.partials;
+        let mut parsed = HashMap::new();
+        for (name, path) in &config.skeletons {
+            parsed.insert(name.clone(), Skeleton::read(path)?.include(partials)?);
+        }
+        let mut named = HashMap::new();
+        for (name, skeleton) in &parsed {
+            named.insert(name.clone(), skeleton.resolve(&parsed, &mut vec![name])?);
+        }
+        Ok(Skeletons {
+            default: Skeleton::read(&config.skeleton)?
+                .include(partials)?
+                .resolve(&parsed, &mut Vec::new())?,
+            named,
+        })
+    }
+}
+
+/// A page to be put into a skeleton
+pub struct Page<'a> {
+    pub title: &'a str,
+    /// The rendered content of the template
+    pub content: &'a str,
+    pub description: Option<&'a str>,
+    /// The attribute list of the page, including its `constants`
+    pub attributes: &'a toml::value::Table,
+    /// The global constants
+    pub constants: &'a toml::value::Table,
+    /// The rendered content of the blocks the page gives
+    pub blocks: &'a HashMap<String, String>,
+}
+
+impl Page<'_> {
+    /// Looks up `name` in the attributes, then in the local constants, then in the global ones
+    fn get(&self, name: &str) -> Option<&toml::Value> {
+        let local = self.attributes.get("constants").and_then(toml::Value::as_table);
+        self.attributes
+            .get(name)
+            .or_else(|| local.and_then(|t| t.get(name)))
+            .or_else(|| self.constants.get(name))
+    }
+    /// The names that `get` can find
+    fn names(&self) -> impl Iterator<Item = &str> {
+        let local = self.attributes.get("constants").and_then(toml::Value::as_table);
+        [self.attributes, self.constants]
+            .into_iter()
+            .chain(local)
+            .flat_map(|t| t.keys())
+            .map(|k| &k[..])
+    }
+    /// Whether `%(if name)` holds
+    fn test(&self, name: &str) -> bool {
+        match name {
+            // The title isn't necessarily in the attributes, it may come from a header
+            "title" => !self.title.is_empty(),
+            "description" => self.description.is_some(),
+            _ => self.get(name).is_some_and(is_truthy),
+        }
+    }
+}
+
 #[derive(Debug)]
 enum Token<'a> {
+    Block(&'a str),
     Content,
     Description,
+    Else,
+    EndBlock,
+    EndIf,
     EndIfDesc,
+    Extends(&'a str),
+    If(&'a str),
     IfDesc,
+    Include(&'a str),
     LiteralText(&'a str),
     Title,
+    Value(&'a str),
 }
 
-fn lex(text: &str) -> Result<Vec<Token>, Box<dyn Error>> {
+/// A token with its byte range
+type Spanned<'a> = (Range<usize>, Token<'a>);
+
+const KEYWORDS: [&str; 12] = [
+    "block",
+    "content",
+    "description",
+    "else",
+    "endblock",
+    "endif",
+    "endifdesc",
+    "extends",
+    "if",
+    "ifdesc",
+    "include",
+    "title",
+];
+
+/// Splits the skeleton into tokens, each paired with its byte range
+fn lex<'a>(src: &Source<'a>) -> Result<Vec<Spanned<'a>>, Error> {
     let mut tokens = Vec::new();
-    let mut rest = text;
+    let mut rest = src.text;
+    let mut offset = 0;
     while let Some(begin) = rest.find("%(") {
-        tokens.push(Token::LiteralText(&rest[..begin]));
+        tokens.push((offset..offset + begin, Token::LiteralText(&rest[..begin])));
+        let token_offset = offset + begin;
         rest = &rest[begin + 2..];
         let end = match rest.find(')') {
             Some(pos) => pos,
-            None => return Err("`%(` without matching `)`".into()),
+            None => {
+                return Err(Diagnostic::error(
+                    Error::Skeleton,
+                    &src.span(token_offset, 2),
+                    "`%(` without matching `)`",
+                    "Close it with `)`, e.g. `%(title)`",
+                ))
+            }
         };
-        let keyword = &rest[..end];
-        let token = match keyword {
-            "content" => Token::Content,
-            "description" => Token::Description,
-            "endifdesc" => Token::EndIfDesc,
-            "ifdesc" => Token::IfDesc,
-            "title" => Token::Title,
-            _ => return Err(format!("Unknown keyword `{}`", keyword).into()),
+        let keyword = rest[..end].trim();
+        // The range of the whole `%(keyword)`
+        let range = token_offset..token_offset + end + 3;
+        let (word, arg) = match keyword.split_once(char::is_whitespace) {
+            Some((word, arg)) => (word, Some(arg.trim())),
+            None => (keyword, None),
+        };
+        let token = match (word, arg) {
+            ("block", Some(name)) if !name.contains(char::is_whitespace) => Token::Block(name),
+            ("block", _) => {
+                return Err(Diagnostic::error(
+                    Error::Skeleton,
+                    &src.span(range.start, range.len()),
+                    "`block` needs a name",
+                    "Write it like `%(block sidebar)`",
+                ))
+            }
+            ("content", None) => Token::Content,
+            ("description", None) => Token::Description,
+            ("else", None) => Token::Else,
+            ("endblock", None) => Token::EndBlock,
+            ("endif", None) => Token::EndIf,
+            ("endifdesc", None) => Token::EndIfDesc,
+            ("extends", Some(name)) if !name.contains(char::is_whitespace) => Token::Extends(name),
+            ("extends", _) => {
+                return Err(Diagnostic::error(
+                    Error::Skeleton,
+                    &src.span(range.start, range.len()),
+                    "`extends` needs the name of a skeleton",
+                    "Write it like `%(extends base)`",
+                ))
+            }
+            ("if", Some(name)) if !name.contains(char::is_whitespace) => Token::If(name),
+            ("if", _) => {
+                return Err(Diagnostic::error(
+                    Error::Skeleton,
+                    &src.span(range.start, range.len()),
+                    "`if` needs the name of an attribute or a constant",
+                    "Write it like `%(if hero)`",
+                ))
+            }
+            ("ifdesc", None) => Token::IfDesc,
+            ("include", Some(path)) => Token::Include(path),
+            ("include", None) => {
+                return Err(Diagnostic::error(
+                    Error::Skeleton,
+                    &src.span(range.start, range.len()),
+                    "`include` needs the path of a partial",
+                    "Write it like `%(include menu.html)`",
+                ))
+            }
+            ("title", None) => Token::Title,
+            (name, None) if !name.is_empty() => Token::Value(name),
+            _ => {
+                let hint = did_you_mean(keyword, KEYWORDS)
+                    .unwrap_or_else(|| format!("Valid keywords are {}", list(KEYWORDS)));
+                return Err(Diagnostic::error(
+                    Error::Skeleton,
+                    &src.span(range.start, range.len()),
+                    format!("Unknown keyword `{}`", keyword),
+                    hint,
+                ));
+            }
         };
-        tokens.push(token);
+        offset = range.end;
+        tokens.push((range, token));
         rest = &rest[end + 1..];
     }
-    tokens.push(Token::LiteralText(rest));
+    tokens.push((offset..src.text.len(), Token::LiteralText(rest)));
     Ok(tokens)
 }
 
-fn parse(tokens: &[Token]) -> Result<Vec<Segment>, Box<dyn Error>> {
-    enum State {
-        TopLevel,
-        IfDesc,
+type Tokens<'s, 'a> = slice::Iter<'s, Spanned<'a>>;
+
+impl Token<'_> {
+    /// The keyword of a token that ends segments
+    fn keyword(&self) -> &'static str {
+        match self {
+            Token::Else => "else",
+            Token::EndBlock => "endblock",
+            Token::EndIf => "endif",
+            _ => "endifdesc",
+        }
     }
+}
 
-    let mut segments: Vec<Segment> = Vec::new();
-    let mut if_segs = Vec::new();
+/// Parses the tokens of a skeleton.
+///
+/// Returns the skeleton it extends, if any, and the segments.
+fn parse(src: &Source, tokens: &[Spanned]) -> Result<(Option<Extends>, Vec<Segment>), Error> {
     let mut iter = tokens.iter();
-    let mut state = State::TopLevel;
-    macro_rules! which {
-        () => {
-            match state {
-                State::TopLevel => &mut segments,
-                State::IfDesc => &mut if_segs,
+    // Only whitespace can come before `extends`
+    let mut extends = None;
+    let mut lookahead = iter.clone();
+    while let Some((range, tok)) = lookahead.next() {
+        match *tok {
+            Token::LiteralText(text) if text.trim().is_empty() => continue,
+            Token::Extends(name) => {
+                extends = Some((name.to_owned(), src.span(range.start, range.len())));
+                iter = lookahead;
             }
-        };
+            _ => {}
+        }
+        break;
+    }
+    let (segments, end) = parse_segments(src, &mut iter)?;
+    let Some((range, tok)) = end else {
+        return Ok((extends, segments));
+    };
+    let (opening, example) = match tok {
+        Token::Else | Token::EndIf => ("if", "%(if name)"),
+        Token::EndBlock => ("block", "%(block name)"),
+        _ => ("ifdesc", "%(ifdesc)"),
+    };
+    Err(Diagnostic::error(
+        Error::Skeleton,
+        &src.span(range.start, range.len()),
+        format!("{} without preceding {}", tok.keyword(), opening),
+        format!("Remove it, or add a `{}` before it", example),
+    ))
+}
+
+/// Parses segments until the end of the skeleton, or until an `else` or an end of a block.
+///
+/// Returns the segments, and the token that ended them, if any.
+fn parse_segments<'s, 'a>(
+    src: &Source,
+    iter: &mut Tokens<'s, 'a>,
+) -> Result<(Vec<Segment>, Option<&'s Spanned<'a>>), Error> {
+    let mut segments = Vec::new();
+    while let Some(item) = iter.next() {
+        let (range, tok) = item;
+        let loc = || src.span(range.start, range.len());
+        match *tok {
+            Token::Block(name) => segments.push(parse_block(src, iter, loc(), name)?),
+            Token::Content => segments.push(Segment::Content),
+            Token::Description => segments.push(Segment::Description(loc())),
+            Token::Else | Token::EndBlock | Token::EndIf | Token::EndIfDesc => {
+                return Ok((segments, Some(item)))
+            }
+            Token::Extends(_) => {
+                return Err(Diagnostic::error(
+                    Error::Skeleton,
+                    &loc(),
+                    "extends must be at the beginning of the skeleton",
+                    "Move it before everything else",
+                ))
+            }
+            Token::If(name) => segments.push(parse_if(src, iter, loc(), name, false)?),
+            Token::IfDesc => segments.push(parse_if(src, iter, loc(), "description", true)?),
+            Token::Include(path) => segments.push(Segment::Include {
+                path: path.to_owned(),
+                loc: loc(),
+            }),
+            Token::LiteralText(text) => segments.push(Segment::Text(text.to_owned())),
+            Token::Title => segments.push(Segment::Title),
+            Token::Value(name) => segments.push(Segment::Value {
+                name: name.to_owned(),
+                loc: loc(),
+            }),
+        }
+    }
+    Ok((segments, None))
+}
+
+/// Makes the error for segments ended by `end`, instead of `closing`.
+///
+/// `opening` is the keyword of the block that began at `loc`.
+fn unclosed(
+    src: &Source,
+    loc: Location,
+    opening: &str,
+    closing: &str,
+    end: Option<&Spanned>,
+) -> Error {
+    match end {
+        None => Diagnostic::error(
+            Error::Skeleton,
+            &loc,
+            format!("{} without matching {}", opening, closing),
+            format!("Close it with `%({})`", closing),
+        ),
+        Some((range, tok)) => {
+            let found = match tok {
+                Token::Else if opening != "block" => "A second else",
+                _ => tok.keyword(),
+            };
+            let article = if opening.starts_with('i') { "an" } else { "a" };
+            Diagnostic::error(
+                Error::Skeleton,
+                &src.span(range.start, range.len()),
+                format!("{} in {} {} block", found, article, opening),
+                format!("Close the {} with `%({})` first", opening, closing),
+            )
+        }
+    }
+}
+
+/// Parses the rest of an if block, which began at `loc`.
+///
+/// `desc` tells whether it's an `ifdesc`, which must be closed with `endifdesc`.
+fn parse_if(
+    src: &Source,
+    iter: &mut Tokens,
+    loc: Location,
+    name: &str,
+    desc: bool,
+) -> Result<Segment, Error> {
+    let (opening, closing) = match desc {
+        true => ("ifdesc", "endifdesc"),
+        false => ("if", "endif"),
+    };
+    let (then, mut end) = parse_segments(src, iter)?;
+    let mut else_ = Vec::new();
+    if let Some((_, Token::Else)) = end {
+        (else_, end) = parse_segments(src, iter)?;
+    }
+    match end {
+        Some((_, Token::EndIf)) if !desc => {}
+        Some((_, Token::EndIfDesc)) if desc => {}
+        _ => return Err(unclosed(src, loc, opening, closing, end)),
+    }
+    Ok(Segment::If {
+        name: name.to_owned(),
+        then,
+        else_,
+    })
+}
+
+/// Parses the rest of a block, which began at `loc`
+fn parse_block(
+    src: &Source,
+    iter: &mut Tokens,
+    loc: Location,
+    name: &str,
+) -> Result<Segment, Error> {
+    let (body, end) = parse_segments(src, iter)?;
+    match end {
+        Some((_, Token::EndBlock)) => Ok(Segment::Block {
+            name: name.to_owned(),
+            loc,
+            body,
+        }),
+        _ => Err(unclosed(src, loc, "block", "endblock", end)),
+    }
+}
+
+/// Collects the blocks in `segments`, including the nested ones
+fn blocks<'a>(segments: &'a [Segment], out: &mut Vec<(&'a str, &'a Location)>) {
+    for seg in segments {
+        match seg {
+            Segment::Block { name, loc, body } => {
+                out.push((name, loc));
+                blocks(body, out);
+            }
+            Segment::If { then, else_, .. } => {
+                blocks(then, out);
+                blocks(else_, out);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Fails if a block name is used more than once in `segments`
+fn check_blocks(segments: &[Segment]) -> Result<(), Error> {
+    let mut names = Vec::new();
+    blocks(segments, &mut names);
+    for (i, (name, loc)) in names.iter().enumerate() {
+        if names[..i].iter().any(|(other, _)| other == name) {
+            return Err(Diagnostic::error(
+                Error::Skeleton,
+                loc,
+                format!("Block `{}` is defined twice", name),
+                "Rename one of them",
+            ));
+        }
     }
+    Ok(())
+}
+
+/// Finds the first include in `segments`
+fn find_include(segments: &[Segment]) -> Option<&Location> {
+    segments.iter().find_map(|seg| match seg {
+        Segment::Include { loc, .. } => Some(loc),
+        Segment::Block { body, .. } => find_include(body),
+        Segment::If { then, else_, .. } => find_include(then).or_else(|| find_include(else_)),
+        _ => None,
+    })
+}
 
-    loop {
-        let tok = iter.next();
-        match tok {
-            Some(&Token::Content) => which!().push(Segment::Content),
-            Some(&Token::Description) => which!().push(Segment::Description),
-            Some(&Token::EndIfDesc) => match state {
-                State::TopLevel => return Err("endifdesc without preceding ifdesc".into()),
-                State::IfDesc => {
-                    let if_segs = std::mem::take(&mut if_segs);
-                    segments.push(Segment::IfDesc(if_segs));
-                    state = State::TopLevel;
+/// Replaces the includes in `segments` with the segments of the partials in `partials`.
+///
+/// `stack` has the canonical paths of the files being included, to detect cycles.
+/// The paths of the partials are added to `files`.
+fn include_all(
+    segments: Vec<Segment>,
+    partials: &Path,
+    stack: &mut Vec<PathBuf>,
+    files: &mut Vec<PathBuf>,
+) -> Result<Vec<Segment>, Error> {
+    let mut out = Vec::new();
+    for seg in segments {
+        match seg {
+            Segment::Include {
+                path: rel_path,
+                loc,
+            } => {
+                let path = partials.join(&rel_path);
+                let text = match fs::read_to_string(&path) {
+                    Ok(text) => text,
+                    Err(e) => {
+                        return Err(Diagnostic::error(
+                            Error::Skeleton,
+                            &loc,
+                            format!("Failed to read partial `{}`: {}", rel_path, e),
+                            format!("Partials are looked up in {:?}", partials),
+                        ))
+                    }
+                };
+                let canonical = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+                if stack.contains(&canonical) {
+                    return Err(Diagnostic::error(
+                        Error::Skeleton,
+                        &loc,
+                        format!("`{}` includes itself", rel_path),
+                        "Partials can't include each other in a circle",
+                    ));
                 }
+                let partial = Skeleton::parse_source(&Source {
+                    path: &path,
+                    text: &text,
+                })?;
+                if let Some((_, loc)) = partial.extends {
+                    return Err(Diagnostic::error(
+                        Error::Skeleton,
+                        &loc,
+                        "extends can't be used in a partial",
+                        "Only skeletons can extend other skeletons",
+                    ));
+                }
+                files.push(path);
+                stack.push(canonical);
+                out.extend(include_all(partial.segments, partials, stack, files)?);
+                stack.pop();
+            }
+            Segment::Block { name, loc, body } => out.push(Segment::Block {
+                name,
+                loc,
+                body: include_all(body, partials, stack, files)?,
+            }),
+            Segment::If { name, then, else_ } => out.push(Segment::If {
+                name,
+                then: include_all(then, partials, stack, files)?,
+                else_: include_all(else_, partials, stack, files)?,
+            }),
+            seg => out.push(seg),
+        }
+    }
+    Ok(out)
+}
+
+/// Replaces the bodies of the blocks in `segments` with the ones in `overrides`
+fn override_blocks(segments: &mut [Segment], overrides: &HashMap<&str, &Vec<Segment>>) {
+    for seg in segments {
+        match seg {
+            Segment::Block { name, body, .. } => match overrides.get(&name[..]) {
+                Some(new) => body.clone_from(new),
+                None => override_blocks(body, overrides),
             },
-            Some(&Token::IfDesc) => match state {
-                State::TopLevel => state = State::IfDesc,
-                State::IfDesc => return Err("Nested ifdescs are not supported".into()),
-            },
-            Some(&Token::LiteralText(text)) => which!().push(Segment::Text(text.to_owned())),
-            Some(&Token::Title) => which!().push(Segment::Title),
-            None => return Ok(segments),
+            Segment::If { then, else_, .. } => {
+                override_blocks(then, overrides);
+                override_blocks(else_, overrides);
+            }
+            _ => {}
         }
     }
 }
 
 impl Skeleton {
-    pub fn parse_file<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
-        let mut f = File::open(path)?;
-        let mut s = String::new();
-        f.read_to_string(&mut s)?;
-        let tokens = lex(&s)?;
+    pub fn parse_file<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Self::read(path.as_ref())?.standalone()
+    }
+    /// Parses a skeleton from a string. Errors refer to it as `<skeleton>`.
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        Self::parse_source(&Source {
+            path: "<skeleton>".as_ref(),
+            text,
+        })?
+        .standalone()
+    }
+    /// Reads and parses the skeleton at `path`, without resolving what it extends
+    fn read(path: &Path) -> Result<Self, Error> {
+        let s = fs::read_to_string(path).map_err(|e| Error::Io(path.to_owned(), e))?;
+        Self::parse_source(&Source { path, text: &s })
+    }
+    fn parse_source(src: &Source) -> Result<Self, Error> {
+        let tokens = lex(src)?;
         debug!("Got tokens: {:#?}", tokens);
-        let segments = parse(&tokens)?;
+        let (extends, segments) = parse(src, &tokens)?;
         debug!("Got segments: {:#?}", segments);
-        Ok(Skeleton { segments })
+        check_blocks(&segments)?;
+        Ok(Skeleton {
+            files: vec![src.path.to_owned()],
+            extends,
+            segments,
+        })
+    }
+    /// Fails if the skeleton extends another one or includes partials, since there is no
+    /// project to look them up in
+    fn standalone(self) -> Result<Self, Error> {
+        let (loc, keyword) = match (&self.extends, find_include(&self.segments)) {
+            (Some((_, loc)), _) => (loc, "extends"),
+            (None, Some(loc)) => (loc, "include"),
+            (None, None) => return Ok(self),
+        };
+        Err(Diagnostic::error(
+            Error::Skeleton,
+            loc,
+            format!("{} can only be used in the skeletons of a project", keyword),
+            "Use the skeleton through a `Project`",
+        ))
     }
-    pub fn out(
+    /// Replaces the includes in the skeleton with the partials in `partials` they refer to
+    fn include(mut self, partials: &Path) -> Result<Self, Error> {
+        let path = &self.files[0];
+        let mut stack = vec![fs::canonicalize(path).unwrap_or_else(|_| path.clone())];
+        let segments = std::mem::take(&mut self.segments);
+        self.segments = include_all(segments, partials, &mut stack, &mut self.files)?;
+        check_blocks(&self.segments)?;
+        Ok(self)
+    }
+    /// Puts the blocks of this skeleton into the one it extends, if any.
+    ///
+    /// The skeletons it can extend are in `parsed`. `chain` has the names of the skeletons that
+    /// are being resolved, which extend this one, to detect cycles.
+    fn resolve<'a>(
         &self,
-        title: &str,
-        content: &str,
-        description: Option<&str>,
-    ) -> Result<String, Box<dyn Error>> {
-        out_segs(&self.segments, title, content, description)
+        parsed: &'a HashMap<String, Skeleton>,
+        chain: &mut Vec<&'a str>,
+    ) -> Result<Skeleton, Error> {
+        let Some((base_name, loc)) = &self.extends else {
+            return Ok(self.clone());
+        };
+        let Some((base_name, base)) = parsed.get_key_value(base_name) else {
+            let hint =
+                did_you_mean(base_name, parsed.keys().map(|k| &k[..])).unwrap_or_else(|| {
+                    "Skeletons are defined in the `[skeletons]` section of the configuration".into()
+                });
+            return Err(Diagnostic::error(
+                Error::Skeleton,
+                loc,
+                format!("Skeleton `{}` does not exist", base_name),
+                hint,
+            ));
+        };
+        if chain.contains(&&base_name[..]) {
+            return Err(Diagnostic::error(
+                Error::Skeleton,
+                loc,
+                format!("Skeleton `{}` extends itself", base_name),
+                format!(
+                    "Skeletons can't extend each other in a circle: {} -> {}",
+                    chain.join(" -> "),
+                    base_name
+                ),
+            ));
+        }
+        chain.push(base_name);
+        let mut resolved = base.resolve(parsed, chain)?;
+        chain.pop();
+        let mut base_blocks = Vec::new();
+        blocks(&resolved.segments, &mut base_blocks);
+        let mut overrides = HashMap::new();
+        for seg in &self.segments {
+            // Everything outside of the blocks is ignored
+            let Segment::Block { name, loc, body } = seg else {
+                continue;
+            };
+            if !base_blocks.iter().any(|(base_block, _)| base_block == name) {
+                let names = base_blocks.iter().map(|(name, _)| *name);
+                let hint = did_you_mean(name, names).unwrap_or_else(|| {
+                    format!("Add it to `{}` with `%(block {})`", base_name, name)
+                });
+                return Err(Diagnostic::error(
+                    Error::Skeleton,
+                    loc,
+                    format!("Skeleton `{}` has no block `{}`", base_name, name),
+                    hint,
+                ));
+            }
+            overrides.insert(&name[..], body);
+        }
+        override_blocks(&mut resolved.segments, &overrides);
+        resolved.files.extend(self.files.iter().cloned());
+        Ok(resolved)
+    }
+    /// The names of the blocks in the skeleton
+    pub(crate) fn block_names(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+        blocks(&self.segments, &mut names);
+        names.into_iter().map(|(name, _)| name).collect()
+    }
+    pub fn out(&self, page: &Page) -> Result<String, Error> {
+        out_segs(&self.segments, page)
     }
 }
 
-fn out_segs(
-    segments: &[Segment],
-    title: &str,
-    content: &str,
-    description: Option<&str>,
-) -> Result<String, Box<dyn Error>> {
+fn out_segs(segments: &[Segment], page: &Page) -> Result<String, Error> {
     let mut out = String::new();
     for seg in segments {
         let string;
         let s = match *seg {
-            Segment::Content => content,
-            Segment::Description => match description {
+            Segment::Block {
+                ref name, ref body, ..
+            } => match page.blocks.get(name) {
+                Some(content) => content,
+                None => {
+                    string = out_segs(body, page)?;
+                    &string
+                }
+            },
+            Segment::Content => page.content,
+            Segment::Include { .. } => unreachable!("Includes are resolved when loading"),
+            Segment::Description(ref loc) => match page.description {
                 Some(desc) => desc,
                 None => {
-                    return Err("Tried to get description when it didn't exist. \
-                         Try putting it in an ifdesc block."
-                        .into())
+                    return Err(Diagnostic::error(
+                        Error::Skeleton,
+                        loc,
+                        "Tried to get description when it didn't exist",
+                        "Put it in an ifdesc block, \
+                         or give every page a `description` attribute",
+                    ))
                 }
             },
-            Segment::IfDesc(ref segs) => match description {
-                Some(desc) => {
-                    string = out_segs(segs, title, content, Some(desc))?;
+            Segment::If {
+                ref name,
+                ref then,
+                ref else_,
+            } => {
+                string = out_segs(if page.test(name) { then } else { else_ }, page)?;
+                &string
+            }
+            Segment::Text(ref text) => text,
+            Segment::Title => page.title,
+            Segment::Value { ref name, ref loc } => match page.get(name) {
+                Some(value) => {
+                    string = value_to_string(value);
                     &string
                 }
-                None => "",
+                None => {
+                    let hint = did_you_mean(name, KEYWORDS.into_iter().chain(page.names()))
+                        .unwrap_or_else(|| {
+                            format!(
+                                "It's not a keyword, nor an attribute or a constant of this page. \
+                                 Put it in `%(if {})`, if not every page has it",
+                                name
+                            )
+                        });
+                    return Err(Diagnostic::error(
+                        Error::Skeleton,
+                        loc,
+                        format!("`{}` does not exist", name),
+                        hint,
+                    ));
+                }
             },
-            Segment::Text(ref text) => text,
-            Segment::Title => title,
         };
         out.push_str(s);
     }
     Ok(out)
 }
+
+#[test]
+fn test_if() {
+    let skeleton = Skeleton::parse(
+        "%(if hero)<hero>%(if wide)wide%(else)narrow%(endif)</hero>%(else)plain%(endif)\
+         %(ifdesc)|%(description)%(endifdesc)",
+    )
+    .unwrap();
+    let attributes = toml::from_str("hero = true\n[constants]\nwide = 0").unwrap();
+    let mut page = Page {
+        title: "Title",
+        content: "",
+        description: None,
+        attributes: &attributes,
+        constants: &toml::from_str("wide = 1").unwrap(),
+        blocks: &HashMap::new(),
+    };
+    assert_eq!(skeleton.out(&page).unwrap(), "<hero>narrow</hero>");
+    page.description = Some("desc");
+    let attributes = toml::from_str("hero = false").unwrap();
+    page.attributes = &attributes;
+    assert_eq!(skeleton.out(&page).unwrap(), "plain|desc");
+}
+
+#[test]
+fn test_if_errors() {
+    use crate::error::expect_diagnostic;
+    let message = |text| expect_diagnostic(Skeleton::parse(text)).msg;
+    assert_eq!(
+        message("%(if a)%(if b)%(endif)"),
+        "if without matching endif"
+    );
+    assert_eq!(message("%(if a)%(endifdesc)"), "endifdesc in an if block");
+    assert_eq!(
+        message("%(if a)%(else)%(else)%(endif)"),
+        "A second else in an if block"
+    );
+    assert_eq!(message("%(else)"), "else without preceding if");
+    assert_eq!(
+        message("%(if)"),
+        "`if` needs the name of an attribute or a constant"
+    );
+}
+
+#[test]
+fn test_values() {
+    use crate::error::expect_diagnostic;
+    let skeleton = Skeleton::parse("%(image)|%(url)|%(year)").unwrap();
+    let attributes =
+        toml::from_str("image = \"a.png\"\n[constants]\nimage = \"b.png\"\nurl = \"/a\"").unwrap();
+    let page = Page {
+        title: "Title",
+        content: "",
+        description: None,
+        attributes: &attributes,
+        constants: &toml::from_str("url = \"/\"\nyear = 2025").unwrap(),
+        blocks: &HashMap::new(),
+    };
+    assert_eq!(skeleton.out(&page).unwrap(), "a.png|/a|2025");
+    let diag = expect_diagnostic(Skeleton::parse("%(yaer)").unwrap().out(&page));
+    assert_eq!(diag.msg, "`yaer` does not exist");
+    assert_eq!(diag.hint.as_deref(), Some("Did you mean `year`?"));
+}
+
+#[test]
+fn test_extends() {
+    use crate::error::expect_diagnostic;
+    let parse = |name: &str, text: &str| {
+        let src = Source {
+            path: name.as_ref(),
+            text,
+        };
+        (name.to_owned(), Skeleton::parse_source(&src).unwrap())
+    };
+    let parsed: HashMap<String, Skeleton> = [
+        parse(
+            "base",
+            "<%(block side)side%(endblock)|%(block main)%(content)%(endblock)>",
+        ),
+        parse(
+            "blog",
+            "%(extends base)\nignored%(block side)[%(block tags)%(endblock)]%(endblock)",
+        ),
+        parse("post", "%(extends blog)%(block tags)tags%(endblock)"),
+        parse("loop", "%(extends loop)"),
+        parse("wrong", "%(extends base)%(block sidebar)%(endblock)"),
+    ]
+    .into();
+    let resolve = |name: &str| parsed[name].resolve(&parsed, &mut vec![name]);
+    let post = resolve("post").unwrap();
+    assert_eq!(post.files, ["base", "blog", "post"].map(PathBuf::from));
+    let mut page = Page {
+        title: "Title",
+        content: "content",
+        description: None,
+        attributes: &toml::value::Table::new(),
+        constants: &toml::value::Table::new(),
+        blocks: &HashMap::new(),
+    };
+    assert_eq!(post.out(&page).unwrap(), "<[tags]|content>");
+    let blocks = [("side".to_owned(), "page side".to_owned())].into();
+    page.blocks = &blocks;
+    assert_eq!(post.out(&page).unwrap(), "<page side|content>");
+    let diag = expect_diagnostic(resolve("loop"));
+    assert_eq!(diag.msg, "Skeleton `loop` extends itself");
+    assert_eq!(
+        diag.hint.as_deref(),
+        Some("Skeletons can't extend each other in a circle: loop -> loop")
+    );
+    let diag = expect_diagnostic(resolve("wrong"));
+    assert_eq!(diag.msg, "Skeleton `base` has no block `sidebar`");
+}
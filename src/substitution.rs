@@ -1,44 +1,146 @@
 use {
-    crate::{config::Config, process::ProcessingContext},
-    log::debug,
+    crate::{
+        config::{Config, OnFailure},
+        error::{did_you_mean, list, Diagnostic, Error, Location, Source},
+        generators::{BuildFailure, PageContext},
+        process::ProcessingContext,
+        util::toml::value_to_string,
+    },
+    log::{debug, warn},
     regex::{Captures, Regex},
-    std::error::Error,
 };
 
-fn get_constant_string(
-    name: &str,
-    config: &Config,
-    local_constants: Option<&toml::value::Table>,
-) -> Result<String, Box<dyn Error>> {
-    let constants = &config.constants;
-    // Check in local constants first, since they shadow global ones
-    if let Some(local) = local_constants {
-        if let Some(const_) = local.get(name) {
-            return Ok(crate::util::toml::value_to_string(const_));
-        }
-    }
-    // Now check in global
-    match constants.get(name) {
-        Some(const_) => Ok(crate::util::toml::value_to_string(const_)),
-        None => Err(format!("Constant `{}` does not exist", name).into()),
+/// Where looking up a path stopped, because the next part of it doesn't exist
+struct Miss<'a, 'p> {
+    /// The value `field` was looked up in, or `None` if the constant itself doesn't exist
+    parent: Option<&'a toml::Value>,
+    /// The path of `parent`
+    prefix: &'p str,
+    field: &'p str,
+}
+
+/// Walks `path` in the local constants, then in the global ones.
+///
+/// This is what [`find`] and [`lookup`] share, so they always agree on what a path refers to.
+fn walk<'a, 'p>(
+    path: &'p str,
+    config: &'a Config,
+    local_constants: Option<&'a toml::value::Table>,
+) -> Result<&'a toml::Value, Miss<'a, 'p>> {
+    let mut fields = path.split('.');
+    let name = fields.next().unwrap_or_default();
+    // Local constants shadow global ones
+    let value = local_constants.and_then(|t| t.get(name)).or_else(|| config.constants.get(name));
+    let mut value = value.ok_or(Miss {
+        parent: None,
+        prefix: "",
+        field: name,
+    })?;
+    let mut prefix = name;
+    for field in fields {
+        let next = match value {
+            toml::Value::Table(table) => table.get(field),
+            toml::Value::Array(array) => field.parse().ok().and_then(|i: usize| array.get(i)),
+            _ => None,
+        };
+        value = next.ok_or(Miss {
+            parent: Some(value),
+            prefix,
+            field,
+        })?;
+        prefix = &path[..prefix.len() + 1 + field.len()];
     }
+    Ok(value)
+}
+
+/// Like [`lookup`], but a constant or field that doesn't exist is `None` rather than an error
+pub(crate) fn find<'a>(
+    path: &str,
+    config: &'a Config,
+    local_constants: Option<&'a toml::value::Table>,
+) -> Option<&'a toml::Value> {
+    walk(path, config, local_constants).ok()
 }
 
+/// Looks up the constant at `path` in the local constants, then in the global ones.
+///
+/// The path can go into tables and arrays, like `contact.phone` or `team.0.name`.
+/// `loc` is where errors point to.
+pub(crate) fn lookup<'a>(
+    path: &str,
+    config: &'a Config,
+    local_constants: Option<&'a toml::value::Table>,
+    loc: &Location,
+) -> Result<&'a toml::Value, Error> {
+    walk(path, config, local_constants).map_err(
+        |Miss {
+             parent,
+             prefix,
+             field,
+         }| {
+            let (msg, hint) = match parent {
+                None => {
+                    let names = local_constants
+                        .into_iter()
+                        .chain([&config.constants])
+                        .flat_map(|t| t.keys());
+                    let hint = did_you_mean(field, names.map(|k| &k[..])).unwrap_or_else(|| {
+                        "Constants are defined in the `[constants]` section of the configuration, \
+                     or in `constants` in the attribute list"
+                            .into()
+                    });
+                    (format!("Constant `{}` does not exist", field), hint)
+                }
+                Some(toml::Value::Table(table)) => {
+                    let keys = || table.keys().map(|k| &k[..]);
+                    let hint = did_you_mean(field, keys())
+                        .unwrap_or_else(|| format!("Its fields are {}", list(keys())));
+                    (format!("`{}` has no field `{}`", prefix, field), hint)
+                }
+                Some(toml::Value::Array(array)) => (
+                    format!("`{}` has no item `{}`", prefix, field),
+                    format!(
+                        "Items are numbered from 0, and it has {} of them",
+                        array.len()
+                    ),
+                ),
+                Some(_) => (
+                    format!("`{}` is neither a table nor an array", prefix),
+                    format!("Use `{}` instead", prefix),
+                ),
+            };
+            Diagnostic::error(Error::Substitution, loc, msg, hint)
+        },
+    )
+}
+
+/// Expands the `%name` constant references in `command`, and the `%%` escapes.
+///
+/// A `%` followed by a percent-encoded byte, like in `caf%C3%A9`, is left as it is.
+/// `offset` is the position of `command` in `src`.
 fn expand_constants(
     command: &str,
+    offset: usize,
+    src: &Source,
     config: &Config,
     local_constants: Option<&toml::value::Table>,
-) -> Result<String, Box<dyn Error>> {
-    let re = Regex::new("%([a-z-]+)").unwrap();
+) -> Result<String, Error> {
+    let re = Regex::new(r"%(%|[A-Za-z_][\w-]*(?:\.[\w-]+)*)").unwrap();
     let mut first_error = None;
     let replaced = re.replace_all(command, |caps: &Captures| {
+        let whole = caps.get(0).expect("No match found.");
         let name = caps.get(1).expect("No capture found.").as_str();
-        match get_constant_string(name, config, local_constants) {
-            Ok(c) => c,
-            Err(e) => {
-                if first_error.is_none() {
-                    first_error = Some(e);
-                }
+        if name == "%" {
+            return "%".to_owned();
+        }
+        if is_percent_encoded(name) {
+            return whole.as_str().to_owned();
+        }
+        let loc = src.span(offset + whole.start(), whole.len());
+        match lookup(name, config, local_constants, &loc) {
+            Ok(value) => value_to_string(value),
+            Err(err) => {
+                first_error.get_or_insert(err);
                 String::new()
             }
         }
@@ -49,74 +151,411 @@ fn expand_constants(
     }
 }
 
+/// Whether `name`, which follows a `%`, starts with a percent-encoded byte like `C3`.
+///
+/// Two lowercase letters start a constant name instead, like in `%face`.
+fn is_percent_encoded(name: &str) -> bool {
+    let Some(byte) = name.as_bytes().get(..2) else {
+        return false;
+    };
+    byte.iter().all(u8::is_ascii_hexdigit) && !byte.iter().all(u8::is_ascii_lowercase)
+}
+
+/// Performs the substitution `command`, which is located at `offset` in `src`.
 pub fn substitute(
     command: &str,
+    offset: usize,
+    src: &Source,
     context: &mut ProcessingContext,
+    title: &str,
     local_constants: Option<&toml::value::Table>,
-) -> Result<String, Box<dyn Error>> {
-    let command = expand_constants(command.trim(), context.config, local_constants)?;
+) -> Result<String, Error> {
+    let offset = offset + command.len() - command.trim_start().len();
+    let original = command.trim();
+    let command = expand_constants(original, offset, src, context.config, local_constants)?;
+    // Points at `len` bytes at `start` in the expanded command.
+    // If constants were expanded, the positions don't correspond to the source,
+    // so it points at the whole command instead.
+    let span = |start: usize, len: usize| {
+        if command == original {
+            src.span(offset + start, len)
+        } else {
+            src.span(offset, original.len())
+        }
+    };
     let re = Regex::new("([a-z]+)(.*)").unwrap();
-    let caps = re.captures(&command).unwrap();
-    let command = caps.get(1).expect("No command").as_str();
-    let rest = caps.get(2).expect("No rest").as_str();
-    debug!("Command: {:?}, Rest: {:?}", command, rest);
-    match command {
+    let Some(caps) = re.captures(&command) else {
+        return Err(Diagnostic::error(
+            Error::Substitution,
+            &span(0, command.len()),
+            "Invalid substitution",
+            "Substitutions look like `{{command arguments}}`, e.g. `{{const phone}}`",
+        ));
+    };
+    let cmd = caps.get(1).expect("No command");
+    let rest = caps.get(2).expect("No rest");
+    debug!("Command: {:?}, Rest: {:?}", cmd.as_str(), rest.as_str());
+    // The trimmed argument part, and its span
+    let arg = rest.as_str().trim();
+    let arg_start = rest.start() + rest.as_str().len() - rest.as_str().trim_start().len();
+    match cmd.as_str() {
         "gen" => {
-            let caps = re.captures(rest).unwrap();
-            let gen_name = &caps[1];
-            let rest = &caps[2];
-            debug!("Gen: {:?}, Rest: {:?}", gen_name, rest);
-            let args = rest.split_whitespace().collect::<Vec<&str>>();
-            gen(gen_name, &args, context)
+            let Some(name) = arg.split_whitespace().next() else {
+                return Err(Diagnostic::error(
+                    Error::Substitution,
+                    &span(cmd.start(), cmd.len()),
+                    "Expected a generator name",
+                    "Generators are used like `{{gen name arguments}}`",
+                ));
+            };
+            if !is_identifier(name) {
+                return Err(Diagnostic::error(
+                    Error::Substitution,
+                    &span(arg_start, name.len()),
+                    format!("Invalid generator name `{}`", name),
+                    "Generator names can contain letters, digits, `_` and `-`, \
+                     and can't start with a digit",
+                ));
+            }
+            let args_start = arg_start + name.len();
+            let args = parse_gen_args(&command[args_start..]).map_err(|(pos, msg)| {
+                Diagnostic::error(
+                    Error::Substitution,
+                    &span(args_start + pos, 1),
+                    msg,
+                    "Quote arguments containing spaces, e.g. `{{gen menu title=\"About us\"}}`",
+                )
+            })?;
+            debug!("Gen: {:?}, Args: {:?}", name, args);
+            let named: Vec<_> = args.iter().filter_map(GenArg::to_env).collect();
+            let args: Vec<String> = args.iter().map(GenArg::to_cli).collect();
+            let args: Vec<&str> = args.iter().map(|arg| &arg[..]).collect();
+            let loc = span(0, command.len());
+            gen(name, &args, &named, loc, context, title, local_constants)
+        }
+        "url" => Ok(format!("<a href=\"{0}\">{0}</a>", arg)),
+        "const" => {
+            let loc = span(arg_start, arg.len());
+            lookup(arg, context.config, local_constants, &loc).map(value_to_string)
+        }
+        _ => Err(Diagnostic::error(
+            Error::Substitution,
+            &span(cmd.start(), cmd.len()),
+            format!("Unknown command `{}`", cmd.as_str()),
+            "Valid commands are `block`, `const`, `for`, `gen`, `if`, `include` and `url`",
+        )),
+    }
+}
+
+/// Whether `name` is a valid generator or argument name.
+///
+/// These are the names Cargo allows for packages: letters, digits, `_` and `-`,
+/// not starting with a digit.
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// An argument of a `gen` substitution
+#[derive(Debug, PartialEq)]
+enum GenArg {
+    Positional(String),
+    /// `key=value`
+    Named(String, String),
+}
+
+impl GenArg {
+    /// The argument as it's passed to the generator on the command line.
+    ///
+    /// Named arguments are passed as `key=value`, like they are written.
+    fn to_cli(&self) -> String {
+        match self {
+            GenArg::Positional(value) => value.clone(),
+            GenArg::Named(key, value) => format!("{}={}", key, value),
+        }
+    }
+    /// The environment variable a named argument is also passed in, like `NOTEN_ARG_MAX_WIDTH`
+    /// for `max-width`
+    fn to_env(&self) -> Option<(String, String)> {
+        match self {
+            GenArg::Positional(_) => None,
+            GenArg::Named(key, value) => {
+                let key = key.to_ascii_uppercase().replace('-', "_");
+                Some((format!("NOTEN_ARG_{}", key), value.clone()))
+            }
         }
-        "url" => Ok(format!("<a href=\"{0}\">{0}</a>", rest.trim())),
-        "const" => get_constant_string(rest.trim(), context.config, local_constants),
-        _ => Err(format!("Unknown command: {:?}", command).into()),
     }
 }
 
+/// Splits the arguments of a `gen` substitution, like a shell would.
+///
+/// Arguments are separated by whitespace. Text in `"` or `'` quotes is taken as-is, except
+/// that `\"` and `\\` are escapes in `"` quotes. Outside of quotes, `\` escapes the next
+/// character. An argument starting with an unquoted name and `=` is a named argument.
+///
+/// On failure, returns the position of the problem in `text`, and an error message.
+fn parse_gen_args(text: &str) -> Result<Vec<GenArg>, (usize, &'static str)> {
+    let mut args = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    loop {
+        while chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            return Ok(args);
+        }
+        let mut word = String::new();
+        let mut key = None;
+        // Whether part of the current word was quoted or escaped, so it can't be a name
+        let mut quoted = false;
+        while let Some((pos, c)) = chars.next_if(|(_, c)| !c.is_whitespace()) {
+            match c {
+                '"' => {
+                    quoted = true;
+                    loop {
+                        match chars.next() {
+                            Some((_, '"')) => break,
+                            Some((_, '\\')) => {
+                                match chars.next_if(|(_, c)| matches!(c, '"' | '\\')) {
+                                    Some((_, c)) => word.push(c),
+                                    None => word.push('\\'),
+                                }
+                            }
+                            Some((_, c)) => word.push(c),
+                            None => return Err((pos, "Unterminated `\"` quote")),
+                        }
+                    }
+                }
+                '\'' => {
+                    quoted = true;
+                    loop {
+                        match chars.next() {
+                            Some((_, '\'')) => break,
+                            Some((_, c)) => word.push(c),
+                            None => return Err((pos, "Unterminated `'` quote")),
+                        }
+                    }
+                }
+                '\\' => {
+                    quoted = true;
+                    match chars.next() {
+                        Some((_, c)) => word.push(c),
+                        None => return Err((pos, "`\\` at the end of the arguments")),
+                    }
+                }
+                '=' if key.is_none() && !quoted && is_identifier(&word) => {
+                    key = Some(std::mem::take(&mut word));
+                }
+                c => word.push(c),
+            }
+        }
+        args.push(match key {
+            Some(key) => GenArg::Named(key, word),
+            None => GenArg::Positional(word),
+        });
+    }
+}
+
+/// Runs a generator, and returns its output.
+///
+/// `named` are the environment variables of the named arguments, and `loc` is the location
+/// of the substitution, which errors point at.
 fn gen(
     gen_name: &str,
     args: &[&str],
+    named: &[(String, String)],
+    loc: Location,
     context: &mut ProcessingContext,
-) -> Result<String, Box<dyn Error>> {
-    use std::{
-        path::Path,
-        process::{Command, Stdio},
+    title: &str,
+    local_constants: Option<&toml::value::Table>,
+) -> Result<String, Error> {
+    let diagnostic = |msg: String| {
+        Diagnostic::new(
+            loc.clone(),
+            format!("Generator `{}` failed: {}", gen_name, msg),
+        )
     };
-
-    let cfg_generators_dir: &Path = context
-        .config
-        .directories
-        .generators
-        .as_ref()
-        .expect("Gen requested but no generators dir")
-        .as_ref();
-
-    let generator_dir = cfg_generators_dir.join(gen_name);
-    if !generator_dir.exists() {
-        panic!("{:?} does not exist.", generator_dir);
+    let fail = |msg: String| Error::Generator(gen_name.to_owned(), diagnostic(msg));
+    let built = match context.generators.build(gen_name, context.config) {
+        Ok(built) => built,
+        Err(BuildFailure::Invalid(msg)) => return Err(fail(msg)),
+        Err(BuildFailure::New(e)) => return Err(e),
+        Err(BuildFailure::Reported) => {
+            return Err(fail(
+                "It couldn't be built, see the output reported for its build".into(),
+            ))
+        }
+    };
+    let settings = context.config.generator(gen_name);
+    let template_path = context.template_path;
+    for dep in built.files.iter().chain(&settings.inputs) {
+        context.template_deps.add_dep(template_path.to_owned(), dep.to_owned());
     }
-    let mut cmd = Command::new("cargo");
-    cmd.current_dir(&generator_dir)
-        .stdout(Stdio::inherit())
-        .arg("build")
-        .arg("--release");
-    let status = cmd.status().expect("Failed to spawn cargo");
-    if !status.success() {
-        panic!("cargo failed");
+    let mut constants = context.config.constants.clone();
+    constants.extend(local_constants.into_iter().flatten().map(|(k, v)| (k.clone(), v.clone())));
+    let page = PageContext {
+        template: template_path,
+        output: context.output_path,
+        project_dir: context.project_dir,
+        title,
+        constants: crate::util::toml::value_to_json(&toml::Value::Table(constants)).to_string(),
+    };
+    let failure = match context.generators.run(gen_name, &built, args, named, &page, &settings) {
+        Ok(output) => return Ok(output),
+        Err(failure) => failure,
+    };
+    let mut diag = diagnostic(failure.msg);
+    if !failure.stderr.is_empty() {
+        diag = diag.note(format!("The generator printed:\n{}", failure.stderr));
     }
-    let gen_cmd_path = generator_dir.join(format!("target/release/{}", gen_name));
-    debug!("Gen command path is {:?}", gen_cmd_path);
-    context
-        .template_deps
-        .add_dep(context.template_path.to_owned(), gen_cmd_path.to_owned());
-    let mut gen_cmd = Command::new(&gen_cmd_path);
-    gen_cmd.args(args);
-    let output = gen_cmd.output().unwrap_or_else(|_| panic!("Failed to spawn {}", gen_name));
-    if output.status.success() {
-        Ok(String::from_utf8(output.stdout)?)
-    } else {
-        panic!("{:?} failed.", gen_cmd_path);
+    match (settings.on_failure, failure.last_output) {
+        (OnFailure::Placeholder, _) => {
+            warn!("Using the placeholder instead: {}", diag);
+            Ok(settings.placeholder)
+        }
+        (OnFailure::Cached, Some(output)) => {
+            warn!("Using the last output instead: {}", diag);
+            Ok(output)
+        }
+        (OnFailure::Cached, None) => Err(Error::Generator(
+            gen_name.to_owned(),
+            diag.hint("There is no earlier output to use instead"),
+        )),
+        (OnFailure::Fail, _) => Err(Error::Generator(gen_name.to_owned(), diag)),
+    }
+}
+
+#[test]
+fn test_expand_constants() {
+    let config = Config {
+        constants: toml::from_str(
+            "contact = { phone = \"123\", Fax_2 = \"456\" }\nface = \"smile\"",
+        )
+        .unwrap(),
+        ..Default::default()
+    };
+    let expand = |text: &str| {
+        let src = Source {
+            path: "a.noten".as_ref(),
+            text,
+        };
+        expand_constants(&text[2..text.len() - 2], 2, &src, &config, None)
+    };
+    assert_eq!(
+        expand("{{gen card %contact.phone %contact.Fax_2. 100%}}").unwrap(),
+        "gen card 123 456. 100%"
+    );
+    assert_eq!(
+        expand("{{url https://ex.com/caf%C3%A9s?q=%e9%2F}}").unwrap(),
+        "url https://ex.com/caf%C3%A9s?q=%e9%2F"
+    );
+    assert_eq!(
+        expand("{{gen card %%face %face 100%%}}").unwrap(),
+        "gen card %face smile 100%"
+    );
+}
+
+#[test]
+fn test_is_identifier() {
+    assert!(is_identifier("menu"));
+    assert!(is_identifier("price-list_2"));
+    assert!(is_identifier("_Gallery"));
+    assert!(!is_identifier("2col"));
+    assert!(!is_identifier("a.b"));
+    assert!(!is_identifier(""));
+}
+
+#[test]
+fn test_parse_gen_args() {
+    use GenArg::{Named, Positional};
+    let pos = |s: &str| Positional(s.into());
+    let named = |k: &str, v: &str| Named(k.into(), v.into());
+    assert_eq!(parse_gen_args("  a  b "), Ok(vec![pos("a"), pos("b")]));
+    assert_eq!(
+        parse_gen_args(r#""a b" 'c "d"' e\ f"#),
+        Ok(vec![pos("a b"), pos("c \"d\""), pos("e f")])
+    );
+    assert_eq!(
+        parse_gen_args(r#""say \"hi\"" "C:\dir""#),
+        Ok(vec![pos("say \"hi\""), pos("C:\\dir")])
+    );
+    assert_eq!(
+        parse_gen_args(r#"title="About us" x=1=2 "k=v" 2x=y =z"#),
+        Ok(vec![
+            named("title", "About us"),
+            named("x", "1=2"),
+            pos("k=v"),
+            pos("2x=y"),
+            pos("=z"),
+        ])
+    );
+    assert_eq!(parse_gen_args(r#"empty="""#), Ok(vec![named("empty", "")]));
+    assert_eq!(
+        parse_gen_args(r#"a "b"#),
+        Err((2, "Unterminated `\"` quote"))
+    );
+    assert_eq!(parse_gen_args("a b'c"), Err((3, "Unterminated `'` quote")));
+    assert_eq!(
+        named("max-width", "5").to_env(),
+        Some(("NOTEN_ARG_MAX_WIDTH".into(), "5".into()))
+    );
+    assert_eq!(pos("a=b").to_env(), None);
+}
+
+#[test]
+fn test_lookup() {
+    use crate::error::expect_diagnostic;
+    let config = Config {
+        constants: toml::from_str(
+            "phone = \"1\"\ncontact = { phone = \"2\" }\n[[team]]\nname = \"Anna\"",
+        )
+        .unwrap(),
+        ..Default::default()
+    };
+    let local: toml::value::Table = toml::from_str("phone = \"3\"").unwrap();
+    let loc = Source {
+        path: "a.noten".as_ref(),
+        text: "",
+    }
+    .location(0);
+    let get = |path| lookup(path, &config, Some(&local), &loc).map(value_to_string);
+    let message = |path| {
+        let diag = expect_diagnostic(lookup(path, &config, None, &loc));
+        (diag.msg, diag.hint.unwrap_or_default())
+    };
+    assert_eq!(get("phone").unwrap(), "3");
+    assert_eq!(get("contact.phone").unwrap(), "2");
+    assert_eq!(get("team.0.name").unwrap(), "Anna");
+    let found = |path| find(path, &config, Some(&local)).map(value_to_string);
+    assert_eq!(found("phone").as_deref(), Some("3"));
+    assert_eq!(found("team.0.name").as_deref(), Some("Anna"));
+    for path in [
+        "missing",
+        "contact.missing",
+        "team.1",
+        "team.first",
+        "phone.area",
+    ] {
+        assert_eq!(found(path), None, "{}", path);
     }
+    assert_eq!(
+        message("contact.phoen"),
+        (
+            "`contact` has no field `phoen`".into(),
+            "Did you mean `phone`?".into()
+        )
+    );
+    assert_eq!(
+        message("team.1.name"),
+        (
+            "`team` has no item `1`".into(),
+            "Items are numbered from 0, and it has 1 of them".into()
+        )
+    );
+    assert_eq!(
+        message("team.0.name.first"),
+        (
+            "`team.0.name` is neither a table nor an array".into(),
+            "Use `team.0.name` instead".into()
+        )
+    );
 }
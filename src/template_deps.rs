@@ -14,26 +14,19 @@ pub struct TemplateDeps {
     pub hash_map: HashMap<PathBuf, Vec<PathBuf>>,
 }
 
+/// Path of the dependency file, relative to the project root
 pub const PATH: &str = ".noten/template-deps.toml";
 
 impl TemplateDeps {
-    pub fn open() -> io::Result<Self> {
-        let mut f = File::open(PATH)?;
+    /// Reads the dependencies saved at `path`.
+    ///
+    /// A file that isn't a table of arrays of paths is an `InvalidData` error.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut f = File::open(path)?;
         let mut s = String::new();
         f.read_to_string(&mut s)?;
-        let table: toml::value::Table = toml::from_str(&s).unwrap();
-        let mut hash_map = HashMap::new();
-        for (k, v) in &table {
-            let k: PathBuf = k.into();
-            hash_map.insert(k.clone(), Vec::new());
-            if let toml::Value::Array(ref vec) = *v {
-                for p in vec {
-                    hash_map.get_mut(&k).unwrap().push(p.as_str().unwrap().into());
-                }
-            } else {
-                panic!("Array expected");
-            }
-        }
+        let hash_map =
+            toml::from_str(&s).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
         Ok(TemplateDeps { hash_map })
     }
     pub fn clear_deps(&mut self, template_path: &Path) {
@@ -52,7 +45,16 @@ impl TemplateDeps {
             }
         }
     }
-    pub fn save(&self) -> io::Result<()> {
+    /// Replaces the dependencies of `template_path` with the ones recorded for it in `other`
+    pub fn replace(&mut self, template_path: &Path, mut other: TemplateDeps) {
+        match other.hash_map.remove(template_path) {
+            Some(deps) => {
+                self.hash_map.insert(template_path.to_owned(), deps);
+            }
+            None => self.clear_deps(template_path),
+        }
+    }
+    pub fn save(&self, path: &Path) -> io::Result<()> {
         let mut table = toml::value::Table::new();
         for (k, v) in &self.hash_map {
             let tp = k.to_string_lossy().into_owned();
@@ -64,7 +66,7 @@ impl TemplateDeps {
             table.insert(tp, toml::Value::Array(array));
         }
         let string = toml::ser::to_string_pretty(&table).unwrap();
-        let mut f = File::create(PATH)?;
+        let mut f = File::create(path)?;
         f.write_all(string.as_bytes())
     }
 }
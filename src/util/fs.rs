@@ -1,4 +1,8 @@
-use std::{fs, io, path::Path};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
 
 pub fn create_dir_if_not_exists<P: AsRef<Path>>(path: P) -> Result<(), io::Error> {
     use std::io::ErrorKind;
@@ -11,3 +15,70 @@ pub fn create_dir_if_not_exists<P: AsRef<Path>>(path: P) -> Result<(), io::Error
         },
     }
 }
+
+/// Recursively collects the paths of all the files under `dir`.
+///
+/// The paths are sorted, so the traversal order is deterministic.
+pub fn walk_files<P: AsRef<Path>>(dir: P) -> Result<Vec<PathBuf>, io::Error> {
+    walk_files_except(dir, &[])
+}
+
+/// Like [`walk_files`], but leaves out the files and directories in `skip`, which must be
+/// canonical paths.
+pub fn walk_files_except<P: AsRef<Path>>(
+    dir: P,
+    skip: &[PathBuf],
+) -> Result<Vec<PathBuf>, io::Error> {
+    let mut files = Vec::new();
+    walk_files_into(dir.as_ref(), skip, &mut files)?;
+    files.sort();
+    Ok(files)
+}
+
+fn walk_files_into(
+    dir: &Path,
+    skip: &[PathBuf],
+    files: &mut Vec<PathBuf>,
+) -> Result<(), io::Error> {
+    for en in fs::read_dir(dir)? {
+        let en = en?;
+        let path = en.path();
+        if !skip.is_empty() && skip.contains(&fs::canonicalize(&path)?) {
+            continue;
+        }
+        if en.file_type()?.is_dir() {
+            walk_files_into(&path, skip, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Removes the empty directories under `dir`, but not `dir` itself.
+pub fn remove_empty_dirs<P: AsRef<Path>>(dir: P) -> Result<(), io::Error> {
+    for en in fs::read_dir(dir)? {
+        let en = en?;
+        if en.file_type()?.is_dir() {
+            let path = en.path();
+            remove_empty_dirs(&path)?;
+            if fs::read_dir(&path)?.next().is_none() {
+                fs::remove_dir(&path)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the last modification time of `path`, or `None` if it can't be determined.
+pub fn modified<P: AsRef<Path>>(path: P) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+/// Whether `src` was modified after `dst`, or `dst` doesn't exist.
+pub fn is_newer<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> bool {
+    match (modified(src), modified(dst)) {
+        (Some(src), Some(dst)) => src > dst,
+        _ => true,
+    }
+}
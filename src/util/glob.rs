@@ -0,0 +1,86 @@
+use {
+    regex::Regex,
+    serde_derive::Deserialize,
+    std::{fmt, path::Path},
+};
+
+/// A shell-style wildcard pattern for matching relative paths.
+///
+/// - `*` matches any number of characters except `/`
+/// - `?` matches a single character except `/`
+/// - `**` matches any number of characters, including `/`
+///
+/// Patterns without a `/` are matched against the file name only,
+/// so `*.png` matches PNG files in any directory.
+#[derive(Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Glob {
+    pattern: String,
+    regex: Regex,
+}
+
+impl Glob {
+    pub fn new(pattern: &str) -> Result<Self, regex::Error> {
+        let mut re = String::from("^");
+        let mut chars = pattern.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '*' if chars.peek() == Some(&'*') => {
+                    chars.next();
+                    // `**/` also matches zero directories
+                    if chars.peek() == Some(&'/') {
+                        chars.next();
+                        re.push_str("(?:.*/)?");
+                    } else {
+                        re.push_str(".*");
+                    }
+                }
+                '*' => re.push_str("[^/]*"),
+                '?' => re.push_str("[^/]"),
+                _ => re.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+            }
+        }
+        re.push('$');
+        Ok(Self {
+            pattern: pattern.to_owned(),
+            regex: Regex::new(&re)?,
+        })
+    }
+    pub fn matches(&self, path: &Path) -> bool {
+        let path = path.to_string_lossy().replace('\\', "/");
+        let subject = if self.pattern.contains('/') {
+            &path[..]
+        } else {
+            path.rsplit('/').next().unwrap_or(&path)
+        };
+        self.regex.is_match(subject)
+    }
+}
+
+impl TryFrom<String> for Glob {
+    type Error = regex::Error;
+    fn try_from(pattern: String) -> Result<Self, Self::Error> {
+        Self::new(&pattern)
+    }
+}
+
+impl fmt::Debug for Glob {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Glob({:?})", self.pattern)
+    }
+}
+
+#[test]
+fn test_glob() {
+    let m = |pat: &str, path: &str| Glob::new(pat).unwrap().matches(path.as_ref());
+    assert!(m("*.png", "logo.png"));
+    assert!(m("*.png", "img/logo.png"));
+    assert!(!m("*.png", "logo.png.bak"));
+    assert!(m("img/*.png", "img/logo.png"));
+    assert!(!m("img/*.png", "img/icons/logo.png"));
+    assert!(m("img/**", "img/icons/logo.png"));
+    assert!(m("**/drafts/*", "drafts/a.txt"));
+    assert!(m("**/drafts/*", "blog/drafts/a.txt"));
+    assert!(m("?.css", "a.css"));
+    assert!(!m("?.css", "ab.css"));
+}
@@ -12,6 +12,37 @@ pub fn value_to_string(value: &toml::Value) -> String {
     }
 }
 
+/// Whether `value` counts as true in a condition.
+///
+/// `false`, `0`, and empty strings, arrays and tables are false, everything else is true.
+pub fn is_truthy(value: &toml::Value) -> bool {
+    match value {
+        toml::Value::String(s) => !s.is_empty(),
+        toml::Value::Integer(i) => *i != 0,
+        toml::Value::Float(f) => *f != 0.0,
+        toml::Value::Boolean(b) => *b,
+        toml::Value::Datetime(_) => true,
+        toml::Value::Array(array) => !array.is_empty(),
+        toml::Value::Table(table) => !table.is_empty(),
+    }
+}
+
+/// Converts a `toml::Value` to JSON. Datetimes become strings.
+pub fn value_to_json(value: &toml::Value) -> serde_json::Value {
+    use serde_json::Value as Json;
+    match value {
+        toml::Value::String(s) => Json::String(s.clone()),
+        toml::Value::Integer(i) => Json::from(*i),
+        toml::Value::Float(f) => Json::from(*f),
+        toml::Value::Boolean(b) => Json::Bool(*b),
+        toml::Value::Datetime(dt) => Json::String(dt.to_string()),
+        toml::Value::Array(array) => Json::Array(array.iter().map(value_to_json).collect()),
+        toml::Value::Table(table) => {
+            Json::Object(table.iter().map(|(k, v)| (k.clone(), value_to_json(v))).collect())
+        }
+    }
+}
+
 #[test]
 fn test_value_to_string() {
     assert_eq!(
@@ -20,3 +51,30 @@ fn test_value_to_string() {
     );
     assert_eq!(value_to_string(&toml::Value::Integer(763)), "763");
 }
+
+#[test]
+fn test_is_truthy() {
+    let table: toml::value::Table = toml::from_str(
+        "yes = true\nno = false\nzero = 0\none = 1\nempty = \"\"\ntext = \"x\"\nnone = []\nday = 2025-01-01",
+    )
+    .unwrap();
+    let truthy: Vec<&str> =
+        table.iter().filter(|(_, v)| is_truthy(v)).map(|(k, _)| &k[..]).collect();
+    assert_eq!(truthy, ["day", "one", "text", "yes"]);
+}
+
+#[test]
+fn test_value_to_json() {
+    let value: toml::Value =
+        toml::from_str("name = \"Anna\"\nage = 30\nborn = 1994-03-01\nphones = [\"1\", \"2\"]")
+            .unwrap();
+    assert_eq!(
+        value_to_json(&value),
+        serde_json::json!({
+            "name": "Anna",
+            "age": 30,
+            "born": "1994-03-01",
+            "phones": ["1", "2"],
+        })
+    );
+}
@@ -0,0 +1,325 @@
+use {
+    crate::{
+        config::Config,
+        generators::Generators,
+        project::{report_failures, Project, Scope},
+        template_deps::TemplateDeps,
+    },
+    log::{debug, error, info},
+    notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher as _},
+    std::{
+        fs,
+        path::{Path, PathBuf},
+        sync::mpsc,
+        time::Duration,
+    },
+};
+
+/// How long to wait for further events after one arrives.
+///
+/// Saving a file usually generates a burst of events, and we only want to rebuild once.
+const DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// What needs to be rebuilt as a result of a batch of changes
+#[derive(Default)]
+struct Changes {
+    /// A skeleton, a partial or the configuration changed, rebuild everything
+    all: bool,
+    /// The configuration changed, and needs to be reloaded
+    config: bool,
+    /// These templates (canonical paths) need to be rebuilt
+    templates: Vec<PathBuf>,
+    /// Something in the input directory changed (e.g. an asset was added, or a file deleted)
+    input: bool,
+}
+
+impl Changes {
+    fn is_empty(&self) -> bool {
+        !self.all && !self.config && self.templates.is_empty() && !self.input
+    }
+}
+
+/// A file or directory that a command generator depends on
+struct GenPath {
+    /// The name of the generator
+    name: String,
+    path: PathBuf,
+    /// Whether this is the `dir` of the generator, rather than a single file
+    is_dir: bool,
+}
+
+/// The canonical paths of the things we are watching
+struct Watched {
+    config: Option<PathBuf>,
+    skeletons: Vec<PathBuf>,
+    input: PathBuf,
+    generators: Option<PathBuf>,
+    partials: Option<PathBuf>,
+    /// The command files, `dir` and `inputs` of the generators in `[generators]`
+    gen_paths: Vec<GenPath>,
+    /// Written by the build itself, so changes in here are ignored
+    ignored: Vec<PathBuf>,
+}
+
+impl Watched {
+    fn new(project: &Project) -> std::io::Result<Self> {
+        let config = project.config();
+        let generators = project.generators();
+        let mut gen_paths = Vec::new();
+        for (name, settings) in &config.generators {
+            let Some(command) = &settings.command else {
+                continue;
+            };
+            let dir = generators.dir(settings);
+            let files = command.iter().map(|arg| dir.join(arg)).filter(|path| path.is_file());
+            // Inputs that don't exist yet can't be watched
+            let paths = files.chain(settings.inputs.iter().cloned()).map(|path| (path, false));
+            let dir = settings.dir.iter().map(|dir| (dir.clone(), true));
+            for (path, is_dir) in dir.chain(paths) {
+                if let Ok(path) = fs::canonicalize(path) {
+                    let name = name.clone();
+                    gen_paths.push(GenPath { name, path, is_dir });
+                }
+            }
+        }
+        let ignored = [&config.directories.output, &project.root().join(".noten")];
+        Ok(Self {
+            // Not available if the configuration wasn't read from a file
+            config: fs::canonicalize(&config.path).ok(),
+            skeletons: [&config.skeleton]
+                .into_iter()
+                .chain(config.skeletons.values())
+                .map(fs::canonicalize)
+                .collect::<Result<_, _>>()?,
+            input: fs::canonicalize(&config.directories.input)?,
+            generators: match &config.directories.generators {
+                Some(dir) if dir.exists() => Some(fs::canonicalize(dir)?),
+                _ => None,
+            },
+            partials: match &config.directories.partials {
+                dir if dir.exists() => Some(fs::canonicalize(dir)?),
+                _ => None,
+            },
+            gen_paths,
+            ignored: ignored.into_iter().filter_map(|dir| fs::canonicalize(dir).ok()).collect(),
+        })
+    }
+    fn start(
+        &self,
+        tx: mpsc::Sender<notify::Result<notify::Event>>,
+    ) -> notify::Result<RecommendedWatcher> {
+        let mut watcher = notify::recommended_watcher(tx)?;
+        // Watch the parent directories of single files, because many editors save by
+        // replacing the file, which would end a watch on the file itself.
+        for file in self.config.iter().chain(&self.skeletons) {
+            if let Some(parent) = file.parent() {
+                watcher.watch(parent, RecursiveMode::NonRecursive)?;
+            }
+        }
+        watcher.watch(&self.input, RecursiveMode::Recursive)?;
+        if let Some(generators) = &self.generators {
+            watcher.watch(generators, RecursiveMode::Recursive)?;
+        }
+        if let Some(partials) = &self.partials {
+            if !partials.starts_with(&self.input) {
+                watcher.watch(partials, RecursiveMode::Recursive)?;
+            }
+        }
+        for gen_path in &self.gen_paths {
+            if gen_path.is_dir {
+                watcher.watch(&gen_path.path, RecursiveMode::Recursive)?;
+            } else if let Some(parent) = gen_path.path.parent() {
+                watcher.watch(parent, RecursiveMode::NonRecursive)?;
+            }
+        }
+        Ok(watcher)
+    }
+    /// Records what needs to be done about the changes in `event`
+    fn record(
+        &self,
+        event: notify::Result<notify::Event>,
+        template_deps: &TemplateDeps,
+        config: &Config,
+        generators: &Generators,
+        during_build: bool,
+        changes: &mut Changes,
+    ) {
+        match event {
+            Ok(ev) if !matches!(ev.kind, EventKind::Access(_)) => {
+                debug!("{:?}", ev);
+                for path in &ev.paths {
+                    self.classify(
+                        path,
+                        template_deps,
+                        config,
+                        generators,
+                        during_build,
+                        changes,
+                    );
+                }
+            }
+            Ok(_) => {}
+            Err(e) => error!("Watch error: {}", e),
+        }
+    }
+    /// Records what needs to be done about a change to `path`
+    fn classify(
+        &self,
+        path: &Path,
+        template_deps: &TemplateDeps,
+        config: &Config,
+        generators: &Generators,
+        during_build: bool,
+        changes: &mut Changes,
+    ) {
+        if self.ignored.iter().any(|dir| path.starts_with(dir)) {
+            return;
+        }
+        // The pages using a command generator are out of date when one of its files changes,
+        // unless the build itself changed it
+        let changed_gens: Vec<&str> = self
+            .gen_paths
+            .iter()
+            .filter(|g| {
+                if g.is_dir {
+                    path.starts_with(&g.path)
+                } else {
+                    path == g.path
+                }
+            })
+            .filter(|_| !during_build)
+            .map(|g| &g.name[..])
+            .collect();
+        if !changed_gens.is_empty() {
+            for (template, deps) in &template_deps.hash_map {
+                let used = generators.used_by(deps, config);
+                if used.iter().any(|name| changed_gens.contains(&&name[..])) {
+                    if let Ok(template) = fs::canonicalize(template) {
+                        changes.templates.push(template);
+                    }
+                }
+            }
+        }
+        if Some(path) == self.config.as_deref() {
+            changes.all = true;
+            changes.config = true;
+        } else if self.skeletons.iter().any(|s| s == path) {
+            changes.all = true;
+        } else if self.partials.as_ref().is_some_and(|p| path.starts_with(p)) {
+            // The pages and skeletons including it are out of date
+            changes.all = true;
+        } else if path.starts_with(&self.input) {
+            changes.input = true;
+            if path.extension() == Some("noten".as_ref()) {
+                changes.templates.push(path.to_owned());
+            }
+        } else if let Some(generators) = &self.generators {
+            let Ok(rel) = path.strip_prefix(generators) else {
+                return;
+            };
+            let mut components = rel.components();
+            let Some(gen_name) = components.next() else {
+                return;
+            };
+            // Cargo writes its build artifacts here, which would trigger an endless loop
+            if components.next() == Some(std::path::Component::Normal("target".as_ref())) {
+                return;
+            }
+            let gen_dir = generators.join(gen_name);
+            for (template, deps) in &template_deps.hash_map {
+                let uses_gen = deps
+                    .iter()
+                    .any(|dep| fs::canonicalize(dep).is_ok_and(|dep| dep.starts_with(&gen_dir)));
+                if uses_gen {
+                    if let Ok(template) = fs::canonicalize(template) {
+                        changes.templates.push(template);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Builds the project, then keeps rebuilding the affected pages whenever something changes.
+///
+/// `on_rebuild` is called with the project after each rebuild. Its configuration may have
+/// been reloaded since the previous call.
+///
+/// Only returns if watching fails.
+pub fn watch(mut project: Project, mut on_rebuild: impl FnMut(&Project)) {
+    report_failures(&project.run(Scope::All));
+    let (tx, rx) = mpsc::channel();
+    let mut watched = match Watched::new(&project) {
+        Ok(watched) => watched,
+        Err(e) => {
+            error!("Failed to resolve the paths to watch: {}", e);
+            return;
+        }
+    };
+    let mut _watcher = match watched.start(tx.clone()) {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            error!("Failed to start watching: {}", e);
+            return;
+        }
+    };
+    info!("Watching for changes. Press Ctrl+C to stop.");
+    // Changes that arrived during the previous rebuild
+    let mut changes = Changes::default();
+    loop {
+        let mut event = if changes.is_empty() {
+            match rx.recv() {
+                Ok(first) => Some(first),
+                Err(_) => return,
+            }
+        } else {
+            None
+        };
+        let template_deps = TemplateDeps::open(&project.template_deps_path()).unwrap_or_default();
+        let generators = project.generators();
+        let config = project.config();
+        while let Some(ev) = event {
+            watched.record(ev, &template_deps, config, &generators, false, &mut changes);
+            event = rx.recv_timeout(DEBOUNCE).ok();
+        }
+        if changes.config {
+            match project.reload() {
+                Ok(()) => {
+                    match Watched::new(&project).and_then(|new| {
+                        let watcher = new.start(tx.clone()).map_err(std::io::Error::other)?;
+                        Ok((new, watcher))
+                    }) {
+                        Ok((new, watcher)) => {
+                            watched = new;
+                            _watcher = watcher;
+                        }
+                        Err(e) => error!("Failed to update the watched paths: {}", e),
+                    }
+                }
+                Err(e) => {
+                    error!("Failed to reload configuration: {}", e);
+                    changes = Changes::default();
+                    continue;
+                }
+            }
+        }
+        if changes.all {
+            info!("Skeleton, partial or configuration changed, rebuilding everything");
+            report_failures(&project.run(Scope::All));
+            on_rebuild(&project);
+        } else if !changes.is_empty() {
+            changes.templates.sort();
+            changes.templates.dedup();
+            report_failures(&project.run(Scope::Only(&changes.templates)));
+            on_rebuild(&project);
+        }
+        changes = Changes::default();
+        // The rebuild changes generator files itself, e.g. when a `build` command writes the
+        // program, which must not trigger another rebuild
+        let template_deps = TemplateDeps::open(&project.template_deps_path()).unwrap_or_default();
+        let (config, generators) = (project.config(), project.generators());
+        while let Ok(ev) = rx.recv_timeout(DEBOUNCE) {
+            watched.record(ev, &template_deps, config, &generators, true, &mut changes);
+        }
+    }
+}
@@ -0,0 +1,519 @@
+use {
+    noten::{
+        config::{Config, Directories, OnFailure},
+        error::Diagnostic,
+        skeleton::Skeleton,
+        BuildOptions, Error, Project,
+    },
+    std::{collections::BTreeMap, fs, num::NonZeroUsize, path::PathBuf},
+};
+
+/// Creates an empty directory for a test project
+fn project_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("noten-test-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("src")).unwrap();
+    dir
+}
+
+/// The diagnostic of the substitution error that `result` failed with
+fn expect_diagnostic<T>(result: Result<T, Error>) -> Diagnostic {
+    match result {
+        Ok(_) => panic!("Expected an error"),
+        Err(Error::Substitution(diag)) => diag,
+        Err(e) => panic!("Expected a substitution error: {}", e),
+    }
+}
+
+fn config() -> Config {
+    toml::from_str(
+        r#"
+        skeleton = "skeleton.html"
+        index = "index"
+        [directories]
+        input = "src"
+        output = "out"
+        [constants]
+        phone = "123"
+        "#,
+    )
+    .unwrap()
+}
+
+#[test]
+fn test_render() {
+    let skeleton = Skeleton::parse("<title>%(title)</title>%(content)").unwrap();
+    let mut constants = toml::value::Table::new();
+    constants.insert("name".into(), "World".into());
+    let out = noten::render("# Hello\n{{const name}}\n", &skeleton, &constants).unwrap();
+    assert_eq!(out, "<title>Hello</title><h1>Hello</h1>\n<p>World</p>\n");
+}
+
+#[test]
+fn test_for_loops() {
+    let skeleton = Skeleton::parse("%(content)").unwrap();
+    let constants: toml::value::Table = toml::from_str(
+        r#"
+        days = ["Mon", "Tue"]
+        hours = { mon = "9-17" }
+        [[team]]
+        name = "Anna"
+        roles = ["massage", "reception"]
+        [[team]]
+        name = "Béla"
+        roles = []
+        "#,
+    )
+    .unwrap();
+    let render = |template: &str| noten::render(template, &skeleton, &constants);
+    let out = render(
+        "# Team\n{{for member in team}}\n- {{member.name}}:{{for role in member.roles}} {{role}}{{endfor}}\n{{endfor}}\n",
+    )
+    .unwrap();
+    assert_eq!(
+        out,
+        "<h1>Team</h1>\n<ul>\n<li>Anna: massage reception</li>\n<li>Béla:</li>\n</ul>\n"
+    );
+    let out = render("# Hours\n{{for day in hours}}{{day.key}}: {{day.value}}{{endfor}}").unwrap();
+    assert_eq!(out, "<h1>Hours</h1>\n<p>mon: 9-17</p>\n");
+    let message = |template| expect_diagnostic(render(template)).msg;
+    assert_eq!(
+        message("# A\n{{for day in days}}"),
+        "for without matching endfor"
+    );
+    assert_eq!(
+        message("# A\n{{for day in dyas}}{{endfor}}"),
+        "Constant `dyas` does not exist"
+    );
+    assert_eq!(
+        message("# A\n{{for m in team}}{{m.nmae}}{{endfor}}"),
+        "`m` has no field `nmae`"
+    );
+}
+
+#[test]
+fn test_conditionals() {
+    let skeleton = Skeleton::parse("%(content)").unwrap();
+    let constants: toml::value::Table = toml::from_str(
+        r#"
+        season = "summer"
+        sauna = true
+        pools = 0
+        contact = { phone = "123" }
+        "#,
+    )
+    .unwrap();
+    let render = |template: &str| noten::render(template, &skeleton, &constants);
+    let out = render(
+        "# A\n{{if sauna}}\nSauna\n{{else}}\nNo sauna\n{{endif}}\n\n{{if pools}}Pools{{else}}No pools{{endif}}\n",
+    )
+    .unwrap();
+    assert_eq!(out, "<h1>A</h1>\n<p>Sauna</p>\n<p>No pools</p>\n");
+    let out = render(
+        "# A\n{{if season == \"summer\"}}Hot{{if missing}}!{{endif}}{{else}}Cold{{endif}}, {{if season != \"winter\"}}open{{endif}}",
+    )
+    .unwrap();
+    assert_eq!(out, "<h1>A</h1>\n<p>Hot, open</p>\n");
+    let out = render(
+        "{\n[constants]\nsauna = false\n}\n# A\n{{if sauna}}Sauna{{else}}{{if contact.phone == \"123\"}}Call{{endif}}{{endif}}",
+    )
+    .unwrap();
+    assert_eq!(out, "<h1>A</h1>\n<p>Call</p>\n");
+    // Missing constants and fields are false, and equal nothing
+    let out = render(
+        "# A\n{{if missing}}1{{endif}}{{if contact.fax}}2{{endif}}{{if sauna.open}}3{{endif}}{{if contact.fax != \"1\"}}4{{endif}}",
+    )
+    .unwrap();
+    assert_eq!(out, "<h1>A</h1>\n<p>4</p>\n");
+    let message = |template| expect_diagnostic(render(template)).msg;
+    assert_eq!(message("# A\n{{if sauna}}"), "if without matching endif");
+    assert_eq!(message("# A\n{{else}}"), "else without preceding if");
+    assert_eq!(
+        message("# A\n{{if season == summer}}{{endif}}"),
+        "Invalid literal `summer`"
+    );
+    assert_eq!(
+        message("# A\n{{if season < 3}}{{endif}}"),
+        "Invalid condition `season < 3`"
+    );
+}
+
+#[test]
+fn test_build() {
+    let dir = project_dir("build");
+    fs::write(dir.join("skeleton.html"), "%(title)|%(content)").unwrap();
+    fs::create_dir_all(dir.join("src/blog")).unwrap();
+    fs::write(
+        dir.join("src/index.noten"),
+        "# Home\nCall {{const phone}}\n",
+    )
+    .unwrap();
+    fs::write(dir.join("src/blog/post.noten"), "# Post\n").unwrap();
+    fs::write(dir.join("src/style.css"), "body {}").unwrap();
+    let project = Project::new(&dir, config());
+    project.build().unwrap();
+    let read = |path: &str| fs::read_to_string(dir.join(path)).unwrap();
+    assert_eq!(
+        read("out/index.html"),
+        "Home|<h1>Home</h1>\n<p>Call 123</p>\n"
+    );
+    assert_eq!(read("out/blog/post.html"), "Post|<h1>Post</h1>\n");
+    assert_eq!(read("out/style.css"), "body {}");
+    assert_eq!(read("index.html"), read("out/index.html"));
+    project.clean().unwrap();
+    assert!(!dir.join("out").exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_empty_input() {
+    let dir = project_dir("empty-input");
+    fs::write(dir.join("skeleton.html"), "%(content)").unwrap();
+    Project::new(&dir, config()).build().unwrap();
+    assert!(!dir.join("out").exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_output_in_input() {
+    let dir = project_dir("output-in-input");
+    fs::create_dir_all(dir.join("partials")).unwrap();
+    fs::write(dir.join("skeleton.html"), "%(include nav.html)|%(content)").unwrap();
+    fs::write(dir.join("partials/nav.html"), "<nav>%(title)</nav>").unwrap();
+    fs::write(dir.join("index.noten"), "# Home\n{{gen hi}}\n").unwrap();
+    fs::write(dir.join("style.css"), "body {}").unwrap();
+    fs::write(dir.join("noten.toml"), "").unwrap();
+    let config = || {
+        let mut config = config();
+        config.directories.input = ".".into();
+        config.generators = toml::from_str("hi = { command = [\"echo\", \"hi\"] }").unwrap();
+        config.path = dir.join("noten.toml");
+        config
+    };
+    // The second build sees what the first one wrote
+    for _ in 0..2 {
+        Project::new(&dir, config()).build().unwrap();
+    }
+    let mut outputs: Vec<_> = fs::read_dir(dir.join("out"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    outputs.sort();
+    assert_eq!(outputs, ["index.html", "style.css"]);
+    assert_eq!(
+        fs::read_to_string(dir.join("out/index.html")).unwrap(),
+        "<nav>Home</nav>|<h1>Home</h1>\n<p>hi</p>\n"
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_corrupt_deps() {
+    let dir = project_dir("corrupt-deps");
+    fs::write(dir.join("skeleton.html"), "old|%(content)").unwrap();
+    fs::write(dir.join("src/index.noten"), "# Home\n").unwrap();
+    // Otherwise the page is always out of date, because its configuration file is missing
+    fs::write(dir.join("noten.toml"), "").unwrap();
+    let config = || Config {
+        path: dir.join("noten.toml"),
+        ..config()
+    };
+    Project::new(&dir, config()).build().unwrap();
+    fs::write(dir.join(".noten/template-deps.toml"), "a = 1\n[b\n").unwrap();
+    // The skeleton is only known to be a dependency from the lost file
+    fs::write(dir.join("skeleton.html"), "new|%(content)").unwrap();
+    Project::new(&dir, config()).build().unwrap();
+    assert_eq!(
+        fs::read_to_string(dir.join("out/index.html")).unwrap(),
+        "new|<h1>Home</h1>\n"
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_default_directories() {
+    let dir = project_dir("default-directories");
+    fs::write(dir.join("skeleton.html"), "%(content)").unwrap();
+    fs::write(dir.join("src/index.noten"), "# Home\n").unwrap();
+    // Built in code rather than read, so `partials` comes from `Directories::default`
+    let config = Config {
+        skeleton: "skeleton.html".into(),
+        index: "index".into(),
+        directories: Directories {
+            input: "src".into(),
+            output: "out".into(),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    Project::new(&dir, config).build().unwrap();
+    assert_eq!(
+        fs::read_to_string(dir.join("out/index.html")).unwrap(),
+        "<h1>Home</h1>\n"
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_gen_args() {
+    let dir = project_dir("gen-args");
+    fs::write(dir.join("skeleton.html"), "%(content)").unwrap();
+    fs::write(
+        dir.join("src/index.noten"),
+        "# Home\n{{gen args \"a b\" max-width=100 title=\"About us\" 'c=d'}}\n",
+    )
+    .unwrap();
+    // Prints the arguments, then the environment variables of the named ones
+    let generators = r#"
+        args = { command = ["sh", "-c", "printf '%s|' \"$@\" \"$NOTEN_ARG_MAX_WIDTH\" \"$NOTEN_ARG_TITLE\" \"${NOTEN_ARG_C-unset}\"", "sh"] }
+        "#;
+    let mut config = config();
+    config.generators = toml::from_str(generators).unwrap();
+    Project::new(&dir, config).build().unwrap();
+    assert_eq!(
+        fs::read_to_string(dir.join("out/index.html")).unwrap(),
+        "<h1>Home</h1>\n<p>a b|max-width=100|title=About us|c=d|100|About us|unset|</p>\n"
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_gen_failures() {
+    let dir = project_dir("gen-failures");
+    fs::write(dir.join("skeleton.html"), "%(content)").unwrap();
+    fs::write(dir.join("data.txt"), "good").unwrap();
+    let generators = r#"
+        broken = { command = ["sh", "-c", "echo oops >&2; exit 1"], on-failure = "placeholder", placeholder = "n/a" }
+        data = { command = ["cat", "data.txt"], cacheable = false }
+        "#;
+    let config = || {
+        let mut config = config();
+        config.generators = toml::from_str(generators).unwrap();
+        config
+    };
+    // Each build changes the page, so it's rendered again
+    let build = |body: &str, config: Config| {
+        fs::write(dir.join("src/index.noten"), format!("# Page\n{}\n", body)).unwrap();
+        Project::new(&dir, config).build()
+    };
+    let read = || fs::read_to_string(dir.join("out/index.html")).unwrap();
+    build("{{gen data}} {{gen broken}}", config()).unwrap();
+    assert_eq!(read(), "<h1>Page</h1>\n<p>good n/a</p>\n");
+    fs::remove_file(dir.join("data.txt")).unwrap();
+    match &build("{{gen data}}", config()).unwrap_err()[..] {
+        [Error::Generator(name, diag)] => {
+            assert_eq!(name, "data");
+            assert!(diag.note.as_ref().is_some_and(|note| note.contains("data.txt")));
+        }
+        failures => panic!("Unexpected errors: {:?}", failures),
+    }
+    // The output of the first build is used, although the policy was `fail` back then
+    let mut config = config();
+    config.overrides.on_gen_failure = Some(OnFailure::Cached);
+    build("{{gen data}}!", config).unwrap();
+    assert_eq!(read(), "<h1>Page</h1>\n<p>good!</p>\n");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_build_errors() {
+    let dir = project_dir("build-errors");
+    fs::write(dir.join("skeleton.html"), "%(content)").unwrap();
+    fs::write(dir.join("src/a.noten"), "# A\n{{const nope}}\n").unwrap();
+    fs::write(dir.join("src/b.noten"), "# B\n").unwrap();
+    let failures = Project::new(&dir, config()).build().unwrap_err();
+    assert_eq!(failures.len(), 1);
+    match &failures[0] {
+        Error::Substitution(diag) => assert_eq!((diag.loc.line, diag.loc.column), (2, 9)),
+        e => panic!("Unexpected error: {}", e),
+    }
+    // The other page is still built
+    assert!(dir.join("out/b.html").exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_skeletons() {
+    let dir = project_dir("skeletons");
+    fs::write(dir.join("skeleton.html"), "page|%(content)").unwrap();
+    fs::write(dir.join("post.html"), "post|%(content)").unwrap();
+    fs::write(dir.join("src/a.noten"), "# A\n").unwrap();
+    fs::write(dir.join("src/b.noten"), "{skeleton = \"post\"}\n# B\n").unwrap();
+    fs::write(dir.join("src/c.noten"), "{skeleton = \"psot\"}\n# C\n").unwrap();
+    let mut config = config();
+    config.skeletons.insert("post".into(), "post.html".into());
+    let failures = Project::new(&dir, config).build().unwrap_err();
+    let read = |path: &str| fs::read_to_string(dir.join(path)).unwrap();
+    assert_eq!(read("out/a.html"), "page|<h1>A</h1>\n");
+    assert_eq!(read("out/b.html"), "post|<h1>B</h1>\n");
+    match &failures[..] {
+        [Error::Attribute(diag)] => {
+            assert_eq!(diag.msg, "Skeleton `psot` does not exist");
+            assert_eq!(diag.hint.as_deref(), Some("Did you mean `post`?"));
+        }
+        _ => panic!("Unexpected errors: {:?}", failures),
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_partials() {
+    let dir = project_dir("partials");
+    fs::create_dir_all(dir.join("partials")).unwrap();
+    fs::write(dir.join("skeleton.html"), "%(include nav.html)|%(content)").unwrap();
+    fs::write(dir.join("partials/nav.html"), "<nav>%(title)</nav>").unwrap();
+    fs::write(dir.join("partials/contact.noten"), "Call {{const phone}}").unwrap();
+    fs::write(dir.join("partials/loop.noten"), "{{include loop.noten}}").unwrap();
+    fs::write(dir.join("src/a.noten"), "# A\n{{include contact.noten}}\n").unwrap();
+    fs::write(dir.join("src/b.noten"), "# B\n{{include loop.noten}}\n").unwrap();
+    let failures = Project::new(&dir, config()).build().unwrap_err();
+    assert_eq!(
+        fs::read_to_string(dir.join("out/a.html")).unwrap(),
+        "<nav>A</nav>|<h1>A</h1>\n<p>Call 123</p>\n"
+    );
+    match &failures[..] {
+        [Error::Substitution(diag)] => {
+            assert_eq!(diag.msg, "`loop.noten` includes itself");
+            assert!(diag.loc.path.ends_with("partials/loop.noten"));
+        }
+        _ => panic!("Unexpected errors: {:?}", failures),
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_parallel_rendering() {
+    // Builds the same pages on `jobs` threads, and returns the outputs, the template
+    // dependencies (relative to the project) and the failures
+    let build = |jobs: usize| {
+        let dir = project_dir(&format!("parallel-{}", jobs));
+        fs::create_dir_all(dir.join("partials")).unwrap();
+        fs::write(dir.join("skeleton.html"), "%(include nav.html)|%(content)").unwrap();
+        fs::write(dir.join("partials/nav.html"), "<nav>%(title)</nav>").unwrap();
+        fs::write(dir.join("partials/contact.noten"), "Call {{const phone}}").unwrap();
+        for i in 0..16 {
+            let page = format!("# Page {}\n{{{{include contact.noten}}}}\n", i);
+            fs::write(dir.join(format!("src/p{}.noten", i)), page).unwrap();
+        }
+        fs::write(
+            dir.join("src/broken.noten"),
+            "# Broken\n{{const missing}}\n",
+        )
+        .unwrap();
+        let options = BuildOptions {
+            jobs: NonZeroUsize::new(jobs),
+            ..Default::default()
+        };
+        let failures = Project::new(&dir, config()).with_options(options).build().unwrap_err();
+        let failures: Vec<_> = failures
+            .iter()
+            .map(|e| match e {
+                Error::Substitution(diag) => {
+                    let path = diag.loc.path.strip_prefix(&dir).unwrap().to_owned();
+                    (path, diag.msg.clone())
+                }
+                e => panic!("Expected a substitution error: {}", e),
+            })
+            .collect();
+        let mut outputs = BTreeMap::new();
+        for entry in fs::read_dir(dir.join("out")).unwrap() {
+            let path = entry.unwrap().path();
+            outputs.insert(
+                path.file_name().unwrap().to_owned(),
+                fs::read_to_string(&path).unwrap(),
+            );
+        }
+        let prefix = format!("{}/", dir.display());
+        let deps = fs::read_to_string(dir.join(".noten/template-deps.toml")).unwrap();
+        let deps: BTreeMap<String, Vec<String>> =
+            toml::from_str(&deps.replace(&prefix, "")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        (outputs, deps, failures)
+    };
+    let (outputs, deps, failures) = build(1);
+    assert_eq!(outputs.len(), 16);
+    assert_eq!(
+        outputs[std::ffi::OsStr::new("p3.html")],
+        "<nav>Page 3</nav>|<h1>Page 3</h1>\n<p>Call 123</p>\n"
+    );
+    assert_eq!(deps.len(), 17);
+    assert!(deps["src/p3.noten"].contains(&"partials/contact.noten".to_owned()));
+    assert_eq!(
+        failures,
+        [(
+            "src/broken.noten".into(),
+            "Constant `missing` does not exist".to_owned()
+        )]
+    );
+    assert_eq!(build(4), (outputs, deps, failures));
+}
+
+#[test]
+fn test_parallel_gen_build() {
+    let dir = project_dir("parallel-gen-build");
+    fs::write(dir.join("skeleton.html"), "%(content)").unwrap();
+    // The build is slow, so the other pages ask for it while it's running
+    let generators = r#"
+        greet = { command = ["echo", "hi"], build = ["sh", "-c", "sleep 0.2; echo built >> builds.txt"] }
+        "#;
+    let mut config = config();
+    config.generators = toml::from_str(generators).unwrap();
+    for i in 0..16 {
+        fs::write(
+            dir.join(format!("src/p{}.noten", i)),
+            "# Page\n{{gen greet}}\n",
+        )
+        .unwrap();
+    }
+    let options = BuildOptions {
+        jobs: NonZeroUsize::new(8),
+        ..Default::default()
+    };
+    Project::new(&dir, config).with_options(options).build().unwrap();
+    assert_eq!(
+        fs::read_to_string(dir.join("out/p7.html")).unwrap(),
+        "<h1>Page</h1>\n<p>hi</p>\n"
+    );
+    assert_eq!(
+        fs::read_to_string(dir.join("builds.txt")).unwrap(),
+        "built\n"
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_gen_cache() {
+    let dir = project_dir("gen-cache");
+    fs::write(dir.join("skeleton.html"), "%(content)").unwrap();
+    let generators = r#"
+        crumb = { command = ["sh", "-c", "echo \"$0\" >> runs.txt; echo \"$0:$NOTEN_TITLE\""] }
+        "#;
+    let read = |path: &str| fs::read_to_string(dir.join(path)).unwrap();
+    let build = |arg: &str| {
+        for page in ["a", "b"] {
+            let template = format!("# {}\n{{{{gen crumb {}}}}}\n", page, arg);
+            fs::write(dir.join(format!("src/{}.noten", page)), template).unwrap();
+        }
+        let mut config = config();
+        config.generators = toml::from_str(generators).unwrap();
+        Project::new(&dir, config).build().unwrap();
+        let entries = fs::read_dir(dir.join(".noten/gen-cache")).unwrap();
+        let mut outputs: Vec<_> =
+            entries.map(|e| fs::read_to_string(e.unwrap().path()).unwrap()).collect();
+        outputs.sort();
+        outputs
+    };
+    // The same call from different pages gets the context of each page
+    let outputs = ["one:a\n", "one:a\n", "one:b\n", "one:b\n"];
+    assert_eq!(build("one"), outputs);
+    assert_eq!(read("out/a.html"), "<h1>a</h1>\n<p>one:a</p>\n");
+    assert_eq!(read("out/b.html"), "<h1>b</h1>\n<p>one:b</p>\n");
+    assert_eq!(read("runs.txt"), "one\none\n");
+    // The pages are rendered again, with the cached outputs
+    assert_eq!(build("one"), outputs);
+    assert_eq!(read("runs.txt"), "one\none\n");
+    // Every page was rendered again, so the entries of `one` are no longer used
+    assert_eq!(build("two"), ["two:a\n", "two:a\n", "two:b\n", "two:b\n"]);
+    assert_eq!(read("runs.txt"), "one\none\ntwo\ntwo\n");
+    fs::remove_dir_all(&dir).unwrap();
+}